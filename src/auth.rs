@@ -0,0 +1,135 @@
+use colored::*;
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::sync::{Mutex, RwLock};
+
+/// Keeps the `Authorization` header fresh by running a user-supplied command
+/// whose stdout is the current token.
+#[derive(Debug)]
+pub struct AuthRefresher {
+    command: String,
+    token: RwLock<Option<String>>,
+    /// Serializes refreshes so a burst of 401s runs the command only once
+    refreshing: Mutex<()>,
+}
+
+impl AuthRefresher {
+    pub fn new(command: &str) -> Self {
+        Self {
+            command: command.to_string(),
+            token: RwLock::new(None),
+            refreshing: Mutex::new(()),
+        }
+    }
+
+    /// The `Authorization` header value for the current token, if any.
+    pub async fn header_value(&self) -> Option<String> {
+        self.token.read().await.clone()
+    }
+
+    /// Run the command and store its output. On failure the previous token is
+    /// kept and the error is logged, so the run carries on.
+    pub async fn refresh(&self) {
+        let _guard = self.refreshing.lock().await;
+        self.run_command().await;
+    }
+
+    /// Refresh after the server rejected `rejected`, unless another request
+    /// already replaced that token in the meantime.
+    pub async fn refresh_after_rejection(&self, rejected: Option<&str>) {
+        let _guard = self.refreshing.lock().await;
+        if self.token.read().await.as_deref() != rejected {
+            return;
+        }
+        self.run_command().await;
+    }
+
+    /// Refresh every `interval` until the task is dropped.
+    pub async fn refresh_every(&self, interval: Duration) {
+        loop {
+            tokio::time::sleep(interval).await;
+            self.refresh().await;
+        }
+    }
+
+    async fn run_command(&self) {
+        match run_shell(&self.command).await {
+            Ok(output) => *self.token.write().await = Some(authorization_value(&output)),
+            Err(e) => eprintln!(
+                "{}Auth refresh command failed: {}{}",
+                "".red(),
+                e,
+                "".clear()
+            ),
+        }
+    }
+}
+
+async fn run_shell(command: &str) -> Result<String, String> {
+    let output = if cfg!(windows) {
+        Command::new("cmd").arg("/C").arg(command).output().await
+    } else {
+        Command::new("sh").arg("-c").arg(command).output().await
+    }
+    .map_err(|e| e.to_string())?;
+
+    if !output.status.success() {
+        return Err(format!("exited with {}", output.status));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if stdout.is_empty() {
+        return Err("printed no token".to_string());
+    }
+    Ok(stdout)
+}
+
+/// A bare token is sent as a bearer token; output that already names a
+/// scheme (`Basic ...`, `Bearer ...`) is used as the header value unchanged.
+fn authorization_value(output: &str) -> String {
+    if output.contains(char::is_whitespace) {
+        output.to_string()
+    } else {
+        format!("Bearer {}", output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_authorization_value() {
+        assert_eq!(authorization_value("abc123"), "Bearer abc123");
+        assert_eq!(
+            authorization_value("Basic dXNlcjpwdw=="),
+            "Basic dXNlcjpwdw=="
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_refresh_keeps_token_on_failure() {
+        let auth = AuthRefresher::new("echo tok1");
+        auth.refresh().await;
+        assert_eq!(auth.header_value().await.as_deref(), Some("Bearer tok1"));
+
+        let failing = AuthRefresher::new("exit 1");
+        *failing.token.write().await = Some("Bearer old".to_string());
+        failing.refresh().await;
+        assert_eq!(failing.header_value().await.as_deref(), Some("Bearer old"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_refresh_after_rejection_skips_replaced_token() {
+        let auth = AuthRefresher::new("echo fresh");
+        *auth.token.write().await = Some("Bearer newer".to_string());
+
+        auth.refresh_after_rejection(Some("Bearer stale")).await;
+        assert_eq!(auth.header_value().await.as_deref(), Some("Bearer newer"));
+
+        auth.refresh_after_rejection(Some("Bearer newer")).await;
+        assert_eq!(auth.header_value().await.as_deref(), Some("Bearer fresh"));
+    }
+}
@@ -0,0 +1,157 @@
+use std::time::Duration;
+
+/// Share of an interval's responses that may ask for backoff before the
+/// next interval's concurrency is cut.
+const THROTTLED_THRESHOLD: f64 = 0.05;
+
+/// Whether a response status asks the client to slow down.
+pub fn is_backpressure(status: u16) -> bool {
+    matches!(status, 429 | 503)
+}
+
+/// A change of concurrency in a --backpressure-aware run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConcurrencyChange {
+    /// When the change took effect, relative to the start of the run
+    pub at: Duration,
+    pub concurrency: u32,
+    /// Percentage of the previous interval's responses that were 429 or 503
+    pub throttled_percent: f64,
+}
+
+/// Concurrency that adapts to backpressure, one interval at a time: halved
+/// after an interval in which more than 5% of responses were 429 or 503,
+/// grown by a tenth (at least one) after a healthy one, always within
+/// `min..=max`. It starts at `max`.
+#[derive(Debug, Clone)]
+pub struct Backpressure {
+    min: u32,
+    max: u32,
+    current: u32,
+    responses: u64,
+    throttled: u64,
+    /// Every concurrency the run used, starting with the initial one
+    pub changes: Vec<ConcurrencyChange>,
+}
+
+impl Backpressure {
+    pub fn new(min: u32, max: u32) -> Self {
+        Self {
+            min,
+            max,
+            current: max,
+            responses: 0,
+            throttled: 0,
+            changes: vec![ConcurrencyChange {
+                at: Duration::ZERO,
+                concurrency: max,
+                throttled_percent: 0.0,
+            }],
+        }
+    }
+
+    /// Concurrency for the current interval.
+    pub fn current(&self) -> u32 {
+        self.current
+    }
+
+    /// Count a response of the current interval.
+    pub fn record(&mut self, status: u16) {
+        self.responses += 1;
+        if is_backpressure(status) {
+            self.throttled += 1;
+        }
+    }
+
+    /// End the current interval `elapsed` into the run, and set the next
+    /// interval's concurrency from its responses. An interval without
+    /// responses leaves it as it was.
+    pub fn adjust(&mut self, elapsed: Duration) -> u32 {
+        let (responses, throttled) = (self.responses, self.throttled);
+        self.responses = 0;
+        self.throttled = 0;
+        if responses == 0 {
+            return self.current;
+        }
+
+        let share = throttled as f64 / responses as f64;
+        let next = if share > THROTTLED_THRESHOLD {
+            (self.current / 2).max(self.min)
+        } else {
+            (self.current + (self.current / 10).max(1)).min(self.max)
+        };
+        if next != self.current {
+            self.current = next;
+            self.changes.push(ConcurrencyChange {
+                at: elapsed,
+                concurrency: next,
+                throttled_percent: share * 100.0,
+            });
+        }
+        self.current
+    }
+
+    /// Lowest and highest concurrency the run used.
+    pub fn range(&self) -> (u32, u32) {
+        let levels = self.changes.iter().map(|change| change.concurrency);
+        (
+            levels.clone().min().unwrap_or(self.max),
+            levels.max().unwrap_or(self.max),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interval(backpressure: &mut Backpressure, ok: u32, throttled: u32, second: u64) -> u32 {
+        for _ in 0..ok {
+            backpressure.record(200);
+        }
+        for _ in 0..throttled {
+            backpressure.record(429);
+        }
+        backpressure.adjust(Duration::from_secs(second))
+    }
+
+    #[test]
+    fn test_backs_off_on_throttling_and_recovers() {
+        let mut backpressure = Backpressure::new(2, 40);
+        assert_eq!(backpressure.current(), 40);
+
+        // Healthy at the ceiling stays there
+        assert_eq!(interval(&mut backpressure, 40, 0, 1), 40);
+        // 10% throttled halves, down to the floor
+        assert_eq!(interval(&mut backpressure, 36, 4, 2), 20);
+        assert_eq!(interval(&mut backpressure, 10, 10, 3), 10);
+        assert_eq!(interval(&mut backpressure, 0, 10, 4), 5);
+        assert_eq!(interval(&mut backpressure, 0, 5, 5), 2);
+        assert_eq!(interval(&mut backpressure, 0, 2, 6), 2);
+        // No responses, no change
+        assert_eq!(interval(&mut backpressure, 0, 0, 7), 2);
+        // Healthy intervals grow by a tenth, at least one
+        assert_eq!(interval(&mut backpressure, 2, 0, 8), 3);
+        // A little throttling is tolerated
+        assert_eq!(interval(&mut backpressure, 20, 1, 9), 4);
+
+        assert_eq!(backpressure.range(), (2, 40));
+        assert_eq!(backpressure.changes.len(), 7);
+        assert_eq!(
+            backpressure.changes[1],
+            ConcurrencyChange {
+                at: Duration::from_secs(2),
+                concurrency: 20,
+                throttled_percent: 10.0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_is_backpressure() {
+        assert!(is_backpressure(429));
+        assert!(is_backpressure(503));
+        assert!(!is_backpressure(500));
+        assert!(!is_backpressure(200));
+    }
+}
@@ -0,0 +1,93 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// A shared token bucket for response bytes: every worker draws from it as
+/// body chunks arrive, so the run as a whole reads no faster than the rate.
+/// Up to one second of unused allowance is banked for bursts.
+#[derive(Debug)]
+pub struct ByteRateLimiter {
+    /// Bytes per second
+    rate: u64,
+    /// Bytes that may be read right away; negative while readers are waiting
+    bucket: Mutex<(f64, Instant)>,
+}
+
+impl ByteRateLimiter {
+    pub fn new(rate: u64) -> Self {
+        Self {
+            rate,
+            bucket: Mutex::new((0.0, Instant::now())),
+        }
+    }
+
+    pub fn rate(&self) -> u64 {
+        self.rate
+    }
+
+    /// Take `bytes` from the bucket, waiting until the rate allows them.
+    /// Bytes are reserved before waiting, so concurrent readers queue up
+    /// behind each other instead of all waking at once.
+    pub async fn consume(&self, bytes: usize) {
+        let wait = self.reserve(bytes, Instant::now());
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    fn reserve(&self, bytes: usize, now: Instant) -> Duration {
+        let rate = self.rate as f64;
+        let mut bucket = self.bucket.lock().unwrap_or_else(|e| e.into_inner());
+        let (available, last) = &mut *bucket;
+        let refill = now.saturating_duration_since(*last).as_secs_f64() * rate;
+        *available = (*available + refill).min(rate) - bytes as f64;
+        *last = now.max(*last);
+
+        if *available >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-*available / rate)
+        }
+    }
+}
+
+/// A byte rate for display, e.g. `1.50 MB/s`.
+pub fn format_bandwidth(bytes_per_second: f64) -> String {
+    format!("{}/s", format_bytes(bytes_per_second))
+}
+
+/// A byte count for display, e.g. `1.50 MB`.
+pub fn format_bytes(bytes: f64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1000.0 && unit < UNITS.len() - 1 {
+        value /= 1000.0;
+        unit += 1;
+    }
+    format!("{:.2} {}", value, UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reserve_queues_readers_behind_each_other() {
+        let start = Instant::now();
+        let limiter = ByteRateLimiter::new(1000);
+
+        assert_eq!(limiter.reserve(500, start), Duration::from_millis(500));
+        assert_eq!(limiter.reserve(500, start), Duration::from_secs(1));
+        // Three seconds on the debt is paid, with at most one second banked
+        let later = start + Duration::from_secs(3);
+        assert_eq!(limiter.reserve(1000, later), Duration::ZERO);
+        assert_eq!(limiter.reserve(250, later), Duration::from_millis(250));
+    }
+
+    #[test]
+    fn test_format_bandwidth() {
+        assert_eq!(format_bandwidth(512.0), "512.00 B/s");
+        assert_eq!(format_bandwidth(1_500_000.0), "1.50 MB/s");
+        assert_eq!(format_bytes(2_000_000_000.0), "2.00 GB");
+    }
+}
@@ -0,0 +1,323 @@
+use colored::*;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::Mutex;
+
+use crate::requests::{make_request_with_retry, method_takes_body, RequestMetrics, RequestParams};
+use crate::runner::{LoadPlan, DEFAULT_MAX_BODY_BYTES};
+use crate::scenario::Target;
+use crate::stats::LatencyRecorder;
+
+/// Where a captured value is read from in a step's response.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CaptureSource {
+    /// `header:<name>` reads a response header
+    Header(String),
+    /// `$.path.to[0].field` reads a value from the JSON body
+    JsonPath(String),
+}
+
+/// A named value extracted from a response and stored in the virtual user's
+/// context, available to later steps as `{captured.<name>}`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Capture {
+    pub name: String,
+    pub source: CaptureSource,
+}
+
+impl Capture {
+    pub fn parse(name: &str, source: &str) -> Result<Self, String> {
+        let source = if let Some(header) = source.strip_prefix("header:") {
+            CaptureSource::Header(header.trim().to_string())
+        } else if source.starts_with('$') {
+            CaptureSource::JsonPath(source.to_string())
+        } else {
+            return Err(format!(
+                "Invalid capture '{}' for {}: expected a JSONPath ($.field) or header:<name>",
+                source, name
+            ));
+        };
+
+        Ok(Self {
+            name: name.to_string(),
+            source,
+        })
+    }
+}
+
+/// Latencies of a chain run as a user journey: per step, and end to end for
+/// journeys that got through every step.
+pub struct JourneyTimes {
+    /// Each step's label and the latencies of its successful requests,
+    /// retries included
+    pub steps: Vec<(String, LatencyRecorder)>,
+    pub completed: LatencyRecorder,
+    /// Journeys that ended early on a failed step or a missing capture
+    pub abandoned: u32,
+}
+
+impl JourneyTimes {
+    pub fn new(steps: &[Target]) -> Self {
+        Self {
+            steps: steps
+                .iter()
+                .enumerate()
+                .map(|(index, step)| (step_label(index, step), LatencyRecorder::new(None)))
+                .collect(),
+            completed: LatencyRecorder::new(None),
+            abandoned: 0,
+        }
+    }
+}
+
+/// `2. POST /cart`: the step's position, method and URL path.
+fn step_label(index: usize, step: &Target) -> String {
+    let method = step.method.clone().unwrap_or(reqwest::Method::GET);
+    // The URL may hold placeholders, so the path is cut out by hand
+    let path = step
+        .url
+        .split_once("://")
+        .and_then(|(_, rest)| rest.find('/').map(|start| &rest[start..]))
+        .unwrap_or("/");
+    format!("{}. {} {}", index + 1, method, path)
+}
+
+async fn record_journey(metrics: &RequestMetrics, record: impl FnOnce(&mut JourneyTimes)) {
+    if let Some(journeys) = &metrics.journeys {
+        record(&mut *journeys.lock().await);
+    }
+}
+
+/// Look up a value with a small JSONPath subset: `$`, `.field` and `[index]`.
+/// Strings are returned unquoted; other values as their JSON text.
+pub fn extract_json_path(value: &Value, path: &str) -> Option<String> {
+    let mut current = value;
+    let mut rest = path.strip_prefix('$')?;
+
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix('.') {
+            let end = after.find(['.', '[']).unwrap_or(after.len());
+            current = current.get(&after[..end])?;
+            rest = &after[end..];
+        } else if let Some(after) = rest.strip_prefix('[') {
+            let end = after.find(']')?;
+            let index: usize = after[..end].trim().parse().ok()?;
+            current = current.get(index)?;
+            rest = &after[end + 1..];
+        } else {
+            return None;
+        }
+    }
+
+    match current {
+        Value::String(s) => Some(s.clone()),
+        Value::Null => None,
+        other => Some(other.to_string()),
+    }
+}
+
+/// Replace every `{captured.<name>}` placeholder with its value from `context`.
+/// Unknown placeholders are left untouched.
+pub fn substitute(template: &str, context: &HashMap<String, String>) -> String {
+    let mut result = template.to_string();
+    for (name, value) in context {
+        result = result.replace(&format!("{{captured.{}}}", name), value);
+    }
+    result
+}
+
+/// Run every step of a chain in order for one virtual user, threading captured
+/// values through a context that lives as long as the user. A failed step, or
+/// a capture that can't be satisfied, ends the chain early.
+pub async fn run_chain(
+    client: &reqwest::Client,
+    plan: &LoadPlan,
+    metrics: &RequestMetrics,
+    total_requests: &Arc<Mutex<u32>>,
+) {
+    let mut context: HashMap<String, String> = HashMap::new();
+    let started = Instant::now();
+
+    for (index, step) in plan.targets.iter().enumerate() {
+        if let Some(offset) = step.start_offset {
+            tokio::time::sleep_until((started + offset).into()).await;
+        }
+
+        let method = step.method.clone().unwrap_or(reqwest::Method::GET);
+        let body = step
+            .body
+            .as_deref()
+            .or(plan.body.as_deref())
+            .filter(|_| method_takes_body(&method))
+            .map(|body| substitute(body, &context));
+
+        let params = RequestParams {
+            method,
+            url: substitute(&step.url, &context),
+            body,
+            headers: step
+                .headers
+                .iter()
+                .map(|(name, value)| (name.clone(), substitute(value, &context)))
+                .collect(),
+            timeout: step.timeout,
+            timeout_growth: plan.timeout_growth,
+            retry_base_delay: plan.retry_base_delay,
+            retry_max_delay: plan.retry_max_delay,
+            // JSON captures need the body even when --read-body is off
+            read_body: plan.read_body.or_else(|| {
+                step.captures
+                    .iter()
+                    .any(|c| matches!(c.source, CaptureSource::JsonPath(_)))
+                    .then_some(DEFAULT_MAX_BODY_BYTES)
+            }),
+            auth: plan.auth.clone(),
+            correlation: plan.correlation_header(),
+            expect_headers: plan.expect_headers.clone(),
+            response_schema: plan.response_schema.clone(),
+            body_size: plan.body_size,
+            timing_breakdown: plan.timing_breakdown,
+            compress_body: plan.compress_request,
+            fail_on: plan.fail_on,
+            #[cfg(feature = "ntlm")]
+            ntlm: plan.ntlm.clone(),
+        };
+
+        {
+            let mut total = total_requests.lock().await;
+            *total += 1;
+        }
+
+        let step_started = Instant::now();
+        let response = match make_request_with_retry(client, &params, plan.retries, metrics).await {
+            Ok(response) => response,
+            Err(e) => {
+                eprintln!("{}Chain step failed: {}{}", "".red(), e, "".clear());
+                record_journey(metrics, |journeys| journeys.abandoned += 1).await;
+                return;
+            }
+        };
+        let step_latency = step_started.elapsed();
+        record_journey(metrics, |journeys| {
+            journeys.steps[index].1.record(step_latency)
+        })
+        .await;
+
+        if step.captures.is_empty() {
+            continue;
+        }
+
+        let headers = &response.headers;
+        let json: Option<Value> = response
+            .body
+            .as_deref()
+            .and_then(|body| serde_json::from_slice(body).ok());
+
+        for capture in &step.captures {
+            let value = match &capture.source {
+                CaptureSource::Header(name) => headers
+                    .get(name.as_str())
+                    .and_then(|v| v.to_str().ok())
+                    .map(|v| v.to_string()),
+                CaptureSource::JsonPath(path) => {
+                    json.as_ref().and_then(|json| extract_json_path(json, path))
+                }
+            };
+
+            match value {
+                Some(value) => {
+                    context.insert(capture.name.clone(), value);
+                }
+                None => {
+                    eprintln!(
+                        "{}Chain capture '{}' not found in response from {}{}",
+                        "".red(),
+                        capture.name,
+                        params.url,
+                        "".clear()
+                    );
+                    record_journey(metrics, |journeys| journeys.abandoned += 1).await;
+                    return;
+                }
+            }
+        }
+    }
+
+    let latency = started.elapsed();
+    record_journey(metrics, |journeys| journeys.completed.record(latency)).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[test]
+    fn test_capture_parse() {
+        assert_eq!(
+            Capture::parse("token", "$.auth.token").unwrap().source,
+            CaptureSource::JsonPath("$.auth.token".to_string())
+        );
+        assert_eq!(
+            Capture::parse("session", "header:Set-Cookie")
+                .unwrap()
+                .source,
+            CaptureSource::Header("Set-Cookie".to_string())
+        );
+        assert!(Capture::parse("bad", "auth.token").is_err());
+    }
+
+    #[test]
+    fn test_extract_json_path() {
+        let body = json!({
+            "token": "abc",
+            "user": { "id": 42, "roles": ["admin", "dev"] },
+            "missing": null
+        });
+
+        assert_eq!(extract_json_path(&body, "$.token"), Some("abc".to_string()));
+        assert_eq!(
+            extract_json_path(&body, "$.user.id"),
+            Some("42".to_string())
+        );
+        assert_eq!(
+            extract_json_path(&body, "$.user.roles[1]"),
+            Some("dev".to_string())
+        );
+        assert_eq!(extract_json_path(&body, "$.user.roles[5]"), None);
+        assert_eq!(extract_json_path(&body, "$.missing"), None);
+        assert_eq!(extract_json_path(&body, "$.nope"), None);
+        assert_eq!(extract_json_path(&body, "token"), None);
+    }
+
+    #[test]
+    fn test_step_label() {
+        let mut step = Target::new(
+            "https://shop.example.com/cart/{captured.id}?x=1",
+            Duration::ZERO,
+        );
+        step.method = Some(reqwest::Method::POST);
+        assert_eq!(step_label(1, &step), "2. POST /cart/{captured.id}?x=1");
+
+        let root = Target::new("https://shop.example.com", Duration::ZERO);
+        assert_eq!(step_label(0, &root), "1. GET /");
+    }
+
+    #[test]
+    fn test_substitute() {
+        let mut context = HashMap::new();
+        context.insert("token".to_string(), "abc".to_string());
+
+        assert_eq!(
+            substitute("Bearer {captured.token}", &context),
+            "Bearer abc"
+        );
+        assert_eq!(
+            substitute("{captured.other}/{captured.token}", &context),
+            "{captured.other}/abc"
+        );
+    }
+}
@@ -0,0 +1,47 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Header used by --correlation-header when no name is given.
+pub const DEFAULT_CORRELATION_HEADER: &str = "X-Request-ID";
+
+/// Hands out a unique ID for every request: a random per-run prefix followed
+/// by a sequence number, so IDs never repeat across runs and cost only an
+/// atomic increment to generate.
+#[derive(Debug)]
+pub struct CorrelationIds {
+    /// Name of the header the ID is sent in
+    pub header: String,
+    prefix: String,
+    next: AtomicU64,
+}
+
+impl CorrelationIds {
+    pub fn new(header: &str) -> Self {
+        Self {
+            header: header.to_string(),
+            prefix: format!("{:016x}", rand::random::<u64>()),
+            next: AtomicU64::new(1),
+        }
+    }
+
+    pub fn next_id(&self) -> String {
+        let sequence = self.next.fetch_add(1, Ordering::Relaxed);
+        format!("{}-{:08}", self.prefix, sequence)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ids_are_sequential_within_a_run_and_unique_across_runs() {
+        let ids = CorrelationIds::new(DEFAULT_CORRELATION_HEADER);
+        let first = ids.next_id();
+        let second = ids.next_id();
+
+        assert!(first.ends_with("-00000001"), "{}", first);
+        assert!(second.ends_with("-00000002"), "{}", second);
+        assert_eq!(first[..16], second[..16]);
+        assert_ne!(first, CorrelationIds::new("X-Trace").next_id());
+    }
+}
@@ -0,0 +1,116 @@
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// DNS lookups made by the clients of one run.
+#[derive(Debug, Default)]
+pub struct DnsTimings {
+    lookups: Mutex<Vec<(Duration, bool)>>,
+}
+
+/// Aggregate lookup times for the report.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DnsSummary {
+    pub lookups: usize,
+    pub failures: usize,
+    /// Lookup times, failed lookups included; `None` without any lookups
+    pub min: Option<Duration>,
+    pub avg: Option<Duration>,
+    pub max: Option<Duration>,
+}
+
+impl DnsTimings {
+    pub fn record(&self, elapsed: Duration, success: bool) {
+        let mut lookups = self.lookups.lock().unwrap_or_else(|e| e.into_inner());
+        lookups.push((elapsed, success));
+    }
+
+    /// Forget earlier lookups, so the next summary covers one run only.
+    pub fn reset(&self) {
+        self.lookups
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clear();
+    }
+
+    pub fn summary(&self) -> DnsSummary {
+        let lookups = self.lookups.lock().unwrap_or_else(|e| e.into_inner());
+        let times = lookups.iter().map(|&(elapsed, _)| elapsed);
+        let total: Duration = times.clone().sum();
+        DnsSummary {
+            lookups: lookups.len(),
+            failures: lookups.iter().filter(|&&(_, success)| !success).count(),
+            min: times.clone().min(),
+            avg: (!lookups.is_empty()).then(|| total / lookups.len() as u32),
+            max: times.max(),
+        }
+    }
+}
+
+/// The system resolver, timing every lookup into shared [`DnsTimings`]. A
+/// lookup happens for each new connection, so pooled connections that get
+/// reused don't show up.
+pub struct TimedResolver {
+    timings: Arc<DnsTimings>,
+}
+
+impl TimedResolver {
+    pub fn new(timings: Arc<DnsTimings>) -> Self {
+        Self { timings }
+    }
+}
+
+impl Resolve for TimedResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let timings = self.timings.clone();
+        Box::pin(async move {
+            let started = Instant::now();
+            // The port is filled in by the connector afterwards
+            let result = tokio::net::lookup_host(format!("{}:0", name.as_str())).await;
+            timings.record(started.elapsed(), result.is_ok());
+            let addrs: Addrs = Box::new(result?);
+            Ok(addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary() {
+        let timings = DnsTimings::default();
+        assert_eq!(timings.summary().lookups, 0);
+        assert_eq!(timings.summary().avg, None);
+
+        timings.record(Duration::from_millis(2), true);
+        timings.record(Duration::from_millis(10), true);
+        timings.record(Duration::from_millis(30), false);
+        let summary = timings.summary();
+        assert_eq!(summary.lookups, 3);
+        assert_eq!(summary.failures, 1);
+        assert_eq!(summary.min, Some(Duration::from_millis(2)));
+        assert_eq!(summary.avg, Some(Duration::from_millis(14)));
+        assert_eq!(summary.max, Some(Duration::from_millis(30)));
+
+        timings.reset();
+        assert_eq!(timings.summary().lookups, 0);
+    }
+
+    #[tokio::test]
+    async fn test_resolver_times_lookups() {
+        let timings = Arc::new(DnsTimings::default());
+        let client = reqwest::Client::builder()
+            .dns_resolver(Arc::new(TimedResolver::new(timings.clone())))
+            .build()
+            .unwrap();
+
+        // Nothing listens on port 9, but the name is resolved all the same
+        let _ = client.get("http://localhost:9/").send().await;
+
+        assert_eq!(timings.summary().lookups, 1);
+        assert_eq!(timings.summary().failures, 0);
+    }
+}
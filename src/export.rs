@@ -0,0 +1,227 @@
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A buffered output file, gzip-compressed when its path ends in `.gz`.
+/// Call [`ExportWriter::finish`] once done; dropping it without finishing
+/// can leave a truncated gzip stream.
+pub enum ExportWriter {
+    Plain(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+}
+
+impl ExportWriter {
+    pub fn create(path: &str) -> io::Result<Self> {
+        let file = BufWriter::new(File::create(path)?);
+        if is_gzip_path(path) {
+            Ok(Self::Gzip(GzEncoder::new(file, Compression::default())))
+        } else {
+            Ok(Self::Plain(file))
+        }
+    }
+
+    /// Flush everything to disk, writing the gzip trailer if compressing.
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            Self::Plain(mut file) => file.flush(),
+            Self::Gzip(encoder) => encoder.finish()?.flush(),
+        }
+    }
+}
+
+impl Write for ExportWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Plain(file) => file.write(buf),
+            Self::Gzip(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Plain(file) => file.flush(),
+            Self::Gzip(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Open an already-open file descriptor inherited from the parent process
+/// for writing, through `/dev/fd` so no descriptor ownership changes hands.
+#[cfg(unix)]
+pub fn open_fd(fd: u32) -> io::Result<File> {
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(format!("/dev/fd/{}", fd))
+}
+
+#[cfg(not(unix))]
+pub fn open_fd(_fd: u32) -> io::Result<File> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "writing to a file descriptor is only supported on Unix",
+    ))
+}
+
+/// Read back an output file, decompressing it when its path ends in `.gz`.
+pub fn read_export(path: &str) -> io::Result<String> {
+    let mut content = String::new();
+    if is_gzip_path(path) {
+        GzDecoder::new(File::open(path)?).read_to_string(&mut content)?;
+    } else {
+        File::open(path)?.read_to_string(&mut content)?;
+    }
+    Ok(content)
+}
+
+/// Run metadata available to output path templates.
+pub struct PathContext<'a> {
+    pub timestamp: String,
+    pub tag: Option<&'a str>,
+    pub url_host: Option<&'a str>,
+}
+
+impl PathContext<'_> {
+    fn value(&self, placeholder: &str) -> Result<String, String> {
+        match placeholder {
+            "timestamp" => Ok(self.timestamp.clone()),
+            "tag" => self
+                .tag
+                .map(String::from)
+                .ok_or_else(|| "{tag} requires --tag".to_string()),
+            "url_host" => self
+                .url_host
+                .map(String::from)
+                .ok_or_else(|| "{url_host} needs a target URL with a host".to_string()),
+            other => Err(format!(
+                "unknown placeholder {{{}}} (use {{timestamp}}, {{tag}} or {{url_host}})",
+                other
+            )),
+        }
+    }
+}
+
+/// Expand `{timestamp}`, `{tag}` and `{url_host}` in an output path, e.g.
+/// `report-{tag}-{timestamp}.json`. Unknown placeholders are an error so a
+/// typo can't silently produce a literal file name.
+pub fn expand_path(template: &str, context: &PathContext) -> Result<String, String> {
+    let mut expanded = String::new();
+    let mut rest = template;
+
+    while let Some(open) = rest.find('{') {
+        let close = rest[open..]
+            .find('}')
+            .ok_or_else(|| format!("unclosed placeholder in {}", template))?;
+        expanded.push_str(&rest[..open]);
+        expanded.push_str(&context.value(&rest[open + 1..open + close])?);
+        rest = &rest[open + close + 1..];
+    }
+    expanded.push_str(rest);
+
+    Ok(expanded)
+}
+
+/// A UTC timestamp like `20240131T235959Z` that sorts and is safe in file names.
+pub fn file_timestamp(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, day_secs) = (secs / 86_400, secs % 86_400);
+
+    // Civil date from days since the epoch (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        day_secs / 3600,
+        day_secs % 3600 / 60,
+        day_secs % 60
+    )
+}
+
+fn is_gzip_path(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("gz"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_export(path: &str, content: &str) {
+        let mut writer = ExportWriter::create(path).unwrap();
+        writer.write_all(content.as_bytes()).unwrap();
+        writer.finish().unwrap();
+    }
+
+    #[test]
+    fn test_gzip_export_round_trips() {
+        let dir = tempfile::tempdir().unwrap();
+        let plain = dir.path().join("out.csv");
+        let gzip = dir.path().join("out.csv.gz");
+        let content = "timestamp,duration_ms\n1,12.5\n2,13.0\n";
+
+        write_export(plain.to_str().unwrap(), content);
+        write_export(gzip.to_str().unwrap(), content);
+
+        let decompressed = read_export(gzip.to_str().unwrap()).unwrap();
+        assert_eq!(decompressed, read_export(plain.to_str().unwrap()).unwrap());
+        assert_eq!(decompressed, content);
+    }
+
+    #[test]
+    fn test_is_gzip_path() {
+        assert!(is_gzip_path("results.csv.gz"));
+        assert!(is_gzip_path("RESULTS.GZ"));
+        assert!(!is_gzip_path("results.csv"));
+    }
+
+    #[test]
+    fn test_expand_path() {
+        let context = PathContext {
+            timestamp: "20240131T235959Z".to_string(),
+            tag: Some("baseline"),
+            url_host: Some("api.example.com"),
+        };
+
+        assert_eq!(
+            expand_path("report-{tag}-{timestamp}.json", &context).unwrap(),
+            "report-baseline-20240131T235959Z.json"
+        );
+        assert_eq!(
+            expand_path("out/{url_host}.csv.gz", &context).unwrap(),
+            "out/api.example.com.csv.gz"
+        );
+        assert_eq!(expand_path("plain.json", &context).unwrap(), "plain.json");
+        assert!(expand_path("{seed}.json", &context).is_err());
+        assert!(expand_path("{tag.json", &context).is_err());
+
+        let untagged = PathContext {
+            tag: None,
+            ..context
+        };
+        assert!(expand_path("{tag}.json", &untagged).is_err());
+    }
+
+    #[test]
+    fn test_file_timestamp() {
+        assert_eq!(file_timestamp(UNIX_EPOCH), "19700101T000000Z");
+        assert_eq!(
+            file_timestamp(UNIX_EPOCH + std::time::Duration::from_secs(1_709_251_199)),
+            "20240229T235959Z"
+        );
+    }
+}
@@ -0,0 +1,103 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Latency buckets as exclusive upper bounds with their frame labels; slower
+/// requests fall in [`SLOWEST_BUCKET`].
+const BUCKETS: [(Duration, &str); 13] = [
+    (Duration::from_millis(1), "<1ms"),
+    (Duration::from_millis(2), "1-2ms"),
+    (Duration::from_millis(5), "2-5ms"),
+    (Duration::from_millis(10), "5-10ms"),
+    (Duration::from_millis(25), "10-25ms"),
+    (Duration::from_millis(50), "25-50ms"),
+    (Duration::from_millis(100), "50-100ms"),
+    (Duration::from_millis(250), "100-250ms"),
+    (Duration::from_millis(500), "250-500ms"),
+    (Duration::from_secs(1), "500ms-1s"),
+    (Duration::from_millis(2500), "1-2.5s"),
+    (Duration::from_secs(5), "2.5-5s"),
+    (Duration::from_secs(10), "5-10s"),
+];
+const SLOWEST_BUCKET: &str = ">=10s";
+
+/// The bucket frame for `latency`, e.g. `10-25ms`.
+fn bucket_label(latency: Duration) -> &'static str {
+    BUCKETS
+        .iter()
+        .find(|(bound, _)| latency < *bound)
+        .map_or(SLOWEST_BUCKET, |(_, label)| label)
+}
+
+/// Request counts by folded stack, for --folded: one frame for the request,
+/// one for its outcome and one for its latency bucket, e.g.
+/// `GET https://api.example.com/users;200;10-25ms`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FoldedLatencies {
+    stacks: BTreeMap<String, u64>,
+}
+
+impl FoldedLatencies {
+    /// Count a finished request. `request` is the method and URL, `outcome`
+    /// the status code or, without a response, the kind of error.
+    pub fn record(&mut self, request: &str, outcome: &str, latency: Duration) {
+        let stack = format!(
+            "{};{};{}",
+            frame(request),
+            frame(outcome),
+            bucket_label(latency)
+        );
+        *self.stacks.entry(stack).or_insert(0) += 1;
+    }
+
+    /// One `stack count` line per stack, each under a `root` frame if given.
+    pub fn lines(&self, root: Option<&str>) -> Vec<String> {
+        self.stacks
+            .iter()
+            .map(|(stack, count)| match root {
+                Some(root) => format!("{};{} {}", frame(root), stack, count),
+                None => format!("{} {}", stack, count),
+            })
+            .collect()
+    }
+}
+
+/// A frame with its separator escaped, since URLs may contain `;`.
+fn frame(value: &str) -> String {
+    value.replace(';', "%3B")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lines() {
+        let mut folded = FoldedLatencies::default();
+        let request = "GET https://api.example.com/users;v=2";
+        folded.record(request, "200", Duration::from_millis(12));
+        folded.record(request, "200", Duration::from_millis(24));
+        folded.record(request, "200", Duration::from_millis(25));
+        folded.record(request, "timeout", Duration::from_secs(30));
+
+        assert_eq!(
+            folded.lines(None),
+            vec![
+                "GET https://api.example.com/users%3Bv=2;200;10-25ms 2",
+                "GET https://api.example.com/users%3Bv=2;200;25-50ms 1",
+                "GET https://api.example.com/users%3Bv=2;timeout;>=10s 1",
+            ]
+        );
+        assert_eq!(
+            folded.lines(Some("HTTP/2"))[0],
+            "HTTP/2;GET https://api.example.com/users%3Bv=2;200;10-25ms 2"
+        );
+    }
+
+    #[test]
+    fn test_bucket_label() {
+        assert_eq!(bucket_label(Duration::from_micros(999)), "<1ms");
+        assert_eq!(bucket_label(Duration::from_millis(1)), "1-2ms");
+        assert_eq!(bucket_label(Duration::from_millis(999)), "500ms-1s");
+        assert_eq!(bucket_label(Duration::from_secs(10)), ">=10s");
+    }
+}
@@ -0,0 +1,301 @@
+use reqwest::Method;
+use serde::Deserialize;
+use std::fs;
+use std::time::Duration;
+use url::Url;
+
+use crate::scenario::Target;
+
+/// Headers the client sets itself, or that describe the recorded connection
+/// rather than the request. `Accept-Encoding` is dropped too, since the client
+/// can't decode compressed bodies.
+const SKIPPED_HEADERS: [&str; 6] = [
+    "host",
+    "content-length",
+    "connection",
+    "keep-alive",
+    "transfer-encoding",
+    "accept-encoding",
+];
+
+#[derive(Debug, Deserialize)]
+struct HarFile {
+    log: HarLog,
+}
+
+#[derive(Debug, Deserialize)]
+struct HarLog {
+    #[serde(default)]
+    entries: Vec<HarEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HarEntry {
+    started_date_time: Option<String>,
+    request: HarRequest,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HarRequest {
+    method: String,
+    url: String,
+    #[serde(default)]
+    headers: Vec<HarHeader>,
+    post_data: Option<HarPostData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HarHeader {
+    name: String,
+    value: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HarPostData {
+    #[serde(default)]
+    mime_type: String,
+    text: Option<String>,
+}
+
+/// One request of a recorded session.
+#[derive(Debug, Clone)]
+struct HarStep {
+    method: Method,
+    url: String,
+    body: Option<String>,
+    headers: Vec<(String, String)>,
+    /// When the request started, relative to the first loaded request
+    offset: Option<Duration>,
+}
+
+/// The HTTP requests of a browser-recorded HAR file, in recorded order.
+#[derive(Debug)]
+pub struct HarSession {
+    steps: Vec<HarStep>,
+    /// Entries that weren't plain HTTP(S) requests, such as `data:` or
+    /// `ws:` URLs
+    pub skipped: usize,
+}
+
+impl HarSession {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let content = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        Self::parse(&content).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    fn parse(content: &str) -> Result<Self, String> {
+        let har: HarFile = serde_json::from_str(content).map_err(|e| e.to_string())?;
+        let total = har.log.entries.len();
+
+        let mut first_start = None;
+        let mut steps = Vec::new();
+        for entry in har.log.entries {
+            let Some(mut step) = HarStep::from_request(entry.request) else {
+                continue;
+            };
+            let started = entry.started_date_time.as_deref().and_then(parse_timestamp);
+            if first_start.is_none() {
+                first_start = started;
+            }
+            step.offset = started.zip(first_start).map(|(started, first)| {
+                Duration::from_millis(started.saturating_sub(first) as u64)
+            });
+            steps.push(step);
+        }
+
+        if steps.is_empty() {
+            return Err("no HTTP requests in HAR file".to_string());
+        }
+        Ok(Self {
+            skipped: total - steps.len(),
+            steps,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    /// Resolve every request into a target with `timeout`. With
+    /// `keep_timing`, each target also keeps when it started in the
+    /// recording, so a chain waits for that point before sending it.
+    pub fn targets(&self, timeout: Duration, keep_timing: bool) -> Vec<Target> {
+        self.steps
+            .iter()
+            .map(|step| Target {
+                method: Some(step.method.clone()),
+                body: step.body.clone(),
+                headers: step.headers.clone(),
+                start_offset: step.offset.filter(|_| keep_timing),
+                ..Target::new(&step.url, timeout)
+            })
+            .collect()
+    }
+}
+
+impl HarStep {
+    /// `None` for anything that isn't an HTTP(S) request we can send.
+    fn from_request(request: HarRequest) -> Option<Self> {
+        let url = Url::parse(&request.url).ok()?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return None;
+        }
+        let method = Method::from_bytes(request.method.to_uppercase().as_bytes()).ok()?;
+
+        // HTTP/2 recordings list pseudo-headers such as `:authority`
+        let mut headers: Vec<(String, String)> = request
+            .headers
+            .into_iter()
+            .filter(|header| {
+                !header.name.starts_with(':')
+                    && !SKIPPED_HEADERS.contains(&header.name.to_ascii_lowercase().as_str())
+            })
+            .map(|header| (header.name, header.value))
+            .collect();
+
+        let body = request.post_data.and_then(|post_data| {
+            let has_content_type = headers
+                .iter()
+                .any(|(name, _)| name.eq_ignore_ascii_case("content-type"));
+            if !has_content_type && !post_data.mime_type.is_empty() {
+                headers.push(("Content-Type".to_string(), post_data.mime_type));
+            }
+            post_data.text
+        });
+
+        Some(Self {
+            method,
+            url: request.url,
+            body,
+            headers,
+            offset: None,
+        })
+    }
+}
+
+/// Milliseconds since the Unix epoch for an ISO 8601 timestamp such as
+/// `2024-01-31T23:59:59.123Z` or `2024-01-31T23:59:59.123+01:00`.
+fn parse_timestamp(value: &str) -> Option<i64> {
+    let (date, time) = value.split_once('T')?;
+    let mut date_parts = date.splitn(3, '-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (
+        date_parts.next()??,
+        date_parts.next()??,
+        date_parts.next()??,
+    );
+
+    let (clock, offset_secs) = if let Some(clock) = time.strip_suffix('Z') {
+        (clock, 0)
+    } else {
+        let split = time.rfind(['+', '-'])?;
+        let (clock, offset) = time.split_at(split);
+        let sign = if offset.starts_with('-') { -1 } else { 1 };
+        let (hours, minutes) = offset[1..].split_once(':')?;
+        let offset_secs =
+            (hours.parse::<i64>().ok()? * 3600 + minutes.parse::<i64>().ok()? * 60) * sign;
+        (clock, offset_secs)
+    };
+
+    let mut clock_parts = clock.splitn(3, ':');
+    let hours: i64 = clock_parts.next()?.parse().ok()?;
+    let minutes: i64 = clock_parts.next()?.parse().ok()?;
+    let seconds: f64 = clock_parts.next()?.parse().ok()?;
+
+    // Days since the epoch from a civil date (Howard Hinnant's algorithm)
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+
+    let secs = days * 86_400 + hours * 3600 + minutes * 60 - offset_secs;
+    Some(secs * 1000 + (seconds * 1000.0).round() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HAR: &str = r#"{
+        "log": {
+            "version": "1.2",
+            "entries": [
+                {
+                    "startedDateTime": "2024-01-31T12:00:00.000Z",
+                    "request": {
+                        "method": "GET",
+                        "url": "https://shop.example.com/",
+                        "headers": [
+                            {"name": ":authority", "value": "shop.example.com"},
+                            {"name": "Accept", "value": "text/html"},
+                            {"name": "Accept-Encoding", "value": "gzip, br"}
+                        ]
+                    }
+                },
+                {
+                    "startedDateTime": "2024-01-31T12:00:00.250Z",
+                    "request": {"method": "GET", "url": "data:image/png;base64,AAAA"}
+                },
+                {
+                    "startedDateTime": "2024-01-31T13:00:01.500+01:00",
+                    "request": {
+                        "method": "POST",
+                        "url": "https://shop.example.com/cart",
+                        "headers": [],
+                        "postData": {"mimeType": "application/json", "text": "{\"sku\":1}"}
+                    }
+                }
+            ]
+        }
+    }"#;
+
+    #[test]
+    fn test_parse_skips_non_http_entries() {
+        let session = HarSession::parse(HAR).unwrap();
+        assert_eq!(session.len(), 2);
+        assert_eq!(session.skipped, 1);
+
+        let targets = session.targets(Duration::from_secs(5), false);
+        assert_eq!(targets[0].method, Some(Method::GET));
+        assert_eq!(
+            targets[0].headers,
+            vec![("Accept".to_string(), "text/html".to_string())]
+        );
+        assert_eq!(targets[1].method, Some(Method::POST));
+        assert_eq!(targets[1].body.as_deref(), Some("{\"sku\":1}"));
+        assert_eq!(
+            targets[1].headers,
+            vec![("Content-Type".to_string(), "application/json".to_string())]
+        );
+        assert_eq!(targets[1].start_offset, None);
+    }
+
+    #[test]
+    fn test_targets_keep_recorded_timing() {
+        let session = HarSession::parse(HAR).unwrap();
+        let targets = session.targets(Duration::from_secs(5), true);
+        assert_eq!(targets[0].start_offset, Some(Duration::ZERO));
+        assert_eq!(targets[1].start_offset, Some(Duration::from_millis(1500)));
+    }
+
+    #[test]
+    fn test_parse_rejects_sessions_without_requests() {
+        assert!(HarSession::parse(r#"{"log": {"entries": []}}"#).is_err());
+        assert!(HarSession::parse("not json").is_err());
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(parse_timestamp("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(
+            parse_timestamp("2024-03-01T00:59:59.5+01:00"),
+            Some(1_709_251_199_500)
+        );
+        assert_eq!(parse_timestamp("yesterday"), None);
+    }
+}
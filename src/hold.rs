@@ -0,0 +1,345 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::watch;
+use tokio_rustls::rustls::{self, OwnedTrustAnchor, RootCertStore, ServerName};
+use tokio_rustls::TlsConnector;
+use url::Url;
+
+/// Largest keep-alive response head read before the connection is given up on.
+const MAX_RESPONSE_HEAD: usize = 64 * 1024;
+
+/// A held connection that ended before the hold was over.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectionDrop {
+    /// Which connection dropped, counting from 1
+    pub connection: u32,
+    /// When it dropped, relative to the start of the hold
+    pub at: Duration,
+    /// How long it had been open
+    pub lifetime: Duration,
+    pub reason: String,
+}
+
+/// The outcome of holding connections open.
+#[derive(Debug, Default)]
+pub struct HoldReport {
+    pub requested: u32,
+    pub opened: u32,
+    /// The first error of a connection that couldn't be opened
+    pub open_error: Option<String>,
+    pub keepalives: u64,
+    /// Dropped connections, earliest first
+    pub drops: Vec<ConnectionDrop>,
+    pub elapsed: Duration,
+}
+
+impl HoldReport {
+    /// Connections still open when the hold ended.
+    pub fn alive(&self) -> u32 {
+        self.opened - self.drops.len() as u32
+    }
+}
+
+/// What to hold open, and for how long.
+#[derive(Debug, Clone)]
+pub struct HoldPlan {
+    pub url: Url,
+    pub connections: u32,
+    pub duration: Duration,
+    /// Send a HEAD request on every connection this often
+    pub keepalive: Option<Duration>,
+    /// Limit for opening a connection and for each keep-alive response
+    pub timeout: Duration,
+}
+
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+/// Open `plan.connections` connections at once and keep them open until the
+/// duration is up or shutdown is requested, noting every connection the
+/// server or network drops. Without a keep-alive interval the connections
+/// stay idle, which tests the server's idle timeout.
+pub async fn hold_connections(plan: HoldPlan, shutdown: &watch::Receiver<bool>) -> HoldReport {
+    let started = Instant::now();
+    let deadline = started + plan.duration;
+    let plan = Arc::new(plan);
+
+    let holders: Vec<_> = (1..=plan.connections)
+        .map(|connection| {
+            let plan = plan.clone();
+            let shutdown = shutdown.clone();
+            tokio::spawn(
+                async move { hold_one(&plan, connection, started, deadline, shutdown).await },
+            )
+        })
+        .collect();
+
+    let mut report = HoldReport {
+        requested: plan.connections,
+        ..HoldReport::default()
+    };
+    for holder in holders {
+        match holder.await {
+            Ok(Ok((keepalives, dropped))) => {
+                report.opened += 1;
+                report.keepalives += keepalives;
+                report.drops.extend(dropped);
+            }
+            Ok(Err(e)) => {
+                report.open_error.get_or_insert(e);
+            }
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        }
+    }
+    report.drops.sort_by_key(|drop| drop.at);
+    report.elapsed = started.elapsed();
+    report
+}
+
+/// Hold one connection until `deadline`. Returns the keep-alive requests
+/// sent and how the connection dropped, if it did, or an error when it
+/// couldn't be opened.
+async fn hold_one(
+    plan: &HoldPlan,
+    connection: u32,
+    started: Instant,
+    deadline: Instant,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<(u64, Option<ConnectionDrop>), String> {
+    let mut stream = tokio::time::timeout(plan.timeout, connect(&plan.url))
+        .await
+        .map_err(|_| "connect timed out".to_string())??;
+    let opened = Instant::now();
+    let request = keepalive_request(&plan.url);
+    let deadline = tokio::time::Instant::from_std(deadline);
+    let mut keepalives = 0;
+    let mut buf = [0u8; 1024];
+
+    let reason = loop {
+        let next_ping = match plan.keepalive {
+            Some(interval) => (tokio::time::Instant::now() + interval).min(deadline),
+            None => deadline,
+        };
+        tokio::select! {
+            _ = tokio::time::sleep_until(next_ping) => {
+                if next_ping >= deadline {
+                    return Ok((keepalives, None));
+                }
+                keepalives += 1;
+                match tokio::time::timeout(plan.timeout, ping(&mut stream, &request)).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(reason)) => break reason,
+                    Err(_) => break "keep-alive timed out".to_string(),
+                }
+            }
+            // Nothing is expected while idle, so this only sees the close
+            read = stream.read(&mut buf) => match read {
+                Ok(0) => break "closed by server".to_string(),
+                Ok(_) => {}
+                Err(e) => break e.to_string(),
+            },
+            _ = shutdown.changed() => return Ok((keepalives, None)),
+        }
+    };
+
+    Ok((
+        keepalives,
+        Some(ConnectionDrop {
+            connection,
+            at: started.elapsed(),
+            lifetime: opened.elapsed(),
+            reason,
+        }),
+    ))
+}
+
+/// Open a TCP connection to `url`, with TLS for `https`.
+async fn connect(url: &Url) -> Result<Box<dyn Stream>, String> {
+    let host = url.host_str().ok_or("URL has no host")?;
+    let port = url.port_or_known_default().ok_or("URL has no port")?;
+    let tcp = TcpStream::connect((host.trim_matches(['[', ']']), port))
+        .await
+        .map_err(|e| e.to_string())?;
+    if url.scheme() != "https" {
+        return Ok(Box::new(tcp));
+    }
+
+    let mut roots = RootCertStore::empty();
+    roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+        OwnedTrustAnchor::from_subject_spki_name_constraints(
+            anchor.subject,
+            anchor.spki,
+            anchor.name_constraints,
+        )
+    }));
+    let config = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let name = ServerName::try_from(host.trim_matches(['[', ']'])).map_err(|e| e.to_string())?;
+    let tls = TlsConnector::from(Arc::new(config))
+        .connect(name, tcp)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(Box::new(tls))
+}
+
+/// A HEAD request for `url`, whose response has no body to read past.
+fn keepalive_request(url: &Url) -> Vec<u8> {
+    let path = match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    };
+    let host = match url.port() {
+        Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+        None => url.host_str().unwrap_or_default().to_string(),
+    };
+    format!(
+        "HEAD {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: tide/{}\r\nConnection: keep-alive\r\n\r\n",
+        path,
+        host,
+        env!("CARGO_PKG_VERSION")
+    )
+    .into_bytes()
+}
+
+/// Send a keep-alive request and read the response head. A response that
+/// announces `Connection: close` counts as a drop, since the server is about
+/// to close the connection.
+async fn ping(stream: &mut Box<dyn Stream>, request: &[u8]) -> Result<(), String> {
+    stream.write_all(request).await.map_err(|e| e.to_string())?;
+
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+        let read = stream.read(&mut buf).await.map_err(|e| e.to_string())?;
+        if read == 0 {
+            return Err("closed by server".to_string());
+        }
+        head.extend_from_slice(&buf[..read]);
+        if head.len() > MAX_RESPONSE_HEAD {
+            return Err("keep-alive response head too large".to_string());
+        }
+    }
+
+    let head = String::from_utf8_lossy(&head);
+    let closing = head.lines().skip(1).any(|line| {
+        line.split_once(':').is_some_and(|(name, value)| {
+            name.trim().eq_ignore_ascii_case("connection")
+                && value.trim().eq_ignore_ascii_case("close")
+        })
+    });
+    if closing {
+        return Err("server asked to close".to_string());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    fn plan(addr: std::net::SocketAddr, keepalive: Option<Duration>) -> HoldPlan {
+        HoldPlan {
+            url: Url::parse(&format!("http://{}/health", addr)).unwrap(),
+            connections: 2,
+            duration: Duration::from_millis(400),
+            keepalive,
+            timeout: Duration::from_secs(1),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hold_reports_connections_the_server_closes() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            // Close the first connection soon after it opens, keep the second
+            let (first, _) = listener.accept().await.unwrap();
+            let (second, _) = listener.accept().await.unwrap();
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            drop(first);
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            drop(second);
+        });
+
+        let (_stop, shutdown) = watch::channel(false);
+        let report = hold_connections(plan(addr, None), &shutdown).await;
+
+        assert_eq!(report.opened, 2);
+        assert_eq!(report.alive(), 1);
+        assert_eq!(report.keepalives, 0);
+        assert_eq!(report.drops.len(), 1);
+        assert_eq!(report.drops[0].reason, "closed by server");
+        assert!(report.drops[0].at >= Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn test_hold_sends_keepalives() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            for id in 0..2 {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let mut buf = [0u8; 1024];
+                    let mut answered = 0;
+                    while socket.read(&mut buf).await.unwrap_or(0) > 0 {
+                        assert!(buf.starts_with(b"HEAD /health HTTP/1.1\r\n"));
+                        answered += 1;
+                        // The second connection is told to close on its second ping
+                        let connection = if id == 1 && answered == 2 {
+                            "close"
+                        } else {
+                            "keep-alive"
+                        };
+                        let response = format!(
+                            "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: {}\r\n\r\n",
+                            connection
+                        );
+                        socket.write_all(response.as_bytes()).await.unwrap();
+                    }
+                });
+            }
+        });
+
+        let (_stop, shutdown) = watch::channel(false);
+        let report =
+            hold_connections(plan(addr, Some(Duration::from_millis(100))), &shutdown).await;
+
+        assert_eq!(report.opened, 2);
+        assert_eq!(report.alive(), 1);
+        assert!(report.keepalives >= 5);
+        assert_eq!(report.drops[0].reason, "server asked to close");
+    }
+
+    #[tokio::test]
+    async fn test_hold_counts_connections_that_fail_to_open() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let (_stop, shutdown) = watch::channel(false);
+        let report = hold_connections(plan(addr, None), &shutdown).await;
+
+        assert_eq!(report.opened, 0);
+        assert_eq!(report.alive(), 0);
+        assert!(report.open_error.is_some());
+    }
+
+    #[test]
+    fn test_keepalive_request() {
+        let url = Url::parse("http://example.com:8080/a?b=1").unwrap();
+        assert_eq!(
+            String::from_utf8(keepalive_request(&url)).unwrap(),
+            format!(
+                "HEAD /a?b=1 HTTP/1.1\r\nHost: example.com:8080\r\nUser-Agent: tide/{}\r\n\
+                 Connection: keep-alive\r\n\r\n",
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+    }
+}
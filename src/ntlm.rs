@@ -0,0 +1,565 @@
+//! NTLMv2 authentication for --ntlm. NTLM authenticates a connection rather
+//! than a request: the client sends a negotiate message, the server answers
+//! 401 with a challenge, and the client's authenticate message must go back
+//! over the same connection. Later requests on that connection need no
+//! handshake.
+
+use reqwest::header::{HeaderMap, WWW_AUTHENTICATE};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::secrets::base64_encode;
+
+const SIGNATURE: &[u8; 8] = b"NTLMSSP\0";
+
+const NEGOTIATE_UNICODE: u32 = 0x0000_0001;
+const NEGOTIATE_OEM: u32 = 0x0000_0002;
+const REQUEST_TARGET: u32 = 0x0000_0004;
+const NEGOTIATE_NTLM: u32 = 0x0000_0200;
+const NEGOTIATE_ALWAYS_SIGN: u32 = 0x0000_8000;
+const NEGOTIATE_EXTENDED_SESSIONSECURITY: u32 = 0x0008_0000;
+const NEGOTIATE_128: u32 = 0x2000_0000;
+const NEGOTIATE_56: u32 = 0x8000_0000;
+
+const NEGOTIATE_FLAGS: u32 = NEGOTIATE_UNICODE
+    | NEGOTIATE_OEM
+    | REQUEST_TARGET
+    | NEGOTIATE_NTLM
+    | NEGOTIATE_ALWAYS_SIGN
+    | NEGOTIATE_EXTENDED_SESSIONSECURITY
+    | NEGOTIATE_128
+    | NEGOTIATE_56;
+
+/// `MsvAvTimestamp` in the challenge's target info
+const AV_TIMESTAMP: u16 = 7;
+const AV_EOL: u16 = 0;
+
+/// Seconds between 1601-01-01, the Windows FILETIME epoch, and the Unix epoch.
+const FILETIME_EPOCH_OFFSET: u64 = 11_644_473_600;
+
+/// Credentials given as `[DOMAIN\]user:password`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NtlmCredentials {
+    pub domain: String,
+    pub user: String,
+    password: String,
+}
+
+impl NtlmCredentials {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (account, password) = spec
+            .split_once(':')
+            .ok_or_else(|| "--ntlm expects [DOMAIN\\]user:password".to_string())?;
+        let (domain, user) = account.split_once('\\').unwrap_or(("", account));
+        if user.is_empty() {
+            return Err("--ntlm needs a user name".to_string());
+        }
+        Ok(Self {
+            domain: domain.to_string(),
+            user: user.to_string(),
+            password: password.to_string(),
+        })
+    }
+}
+
+/// The authentication scheme a 401 response offers for NTLM: `NTLM` itself,
+/// or `Negotiate`, which Windows servers accept raw NTLM messages under.
+pub fn offered_scheme(headers: &HeaderMap) -> Option<&'static str> {
+    let offers: Vec<&str> = headers
+        .get_all(WWW_AUTHENTICATE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .collect();
+    let offered = |scheme: &str| {
+        offers.iter().any(|offer| {
+            offer
+                .split_whitespace()
+                .next()
+                .is_some_and(|name| name.eq_ignore_ascii_case(scheme))
+        })
+    };
+    if offered("NTLM") {
+        Some("NTLM")
+    } else if offered("Negotiate") {
+        Some("Negotiate")
+    } else {
+        None
+    }
+}
+
+/// The base64 token of a `WWW-Authenticate: <scheme> <token>` challenge.
+pub fn challenge_token<'a>(headers: &'a HeaderMap, scheme: &str) -> Option<&'a str> {
+    headers
+        .get_all(WWW_AUTHENTICATE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .find_map(|value| {
+            let (name, token) = value.trim().split_once(' ')?;
+            name.eq_ignore_ascii_case(scheme).then(|| token.trim())
+        })
+}
+
+/// The first message of the handshake, ready for an `Authorization` header.
+pub fn negotiate_message() -> String {
+    let mut message = Vec::with_capacity(32);
+    message.extend_from_slice(SIGNATURE);
+    message.extend_from_slice(&1u32.to_le_bytes());
+    message.extend_from_slice(&NEGOTIATE_FLAGS.to_le_bytes());
+    // Empty domain and workstation fields
+    message.extend_from_slice(&[0; 16]);
+    base64_encode(&message)
+}
+
+/// What the server's challenge message carries.
+#[derive(Debug, PartialEq)]
+pub struct Challenge {
+    pub server_challenge: [u8; 8],
+    pub flags: u32,
+    pub target_info: Vec<u8>,
+}
+
+impl Challenge {
+    /// Decode a base64 challenge message.
+    pub fn parse(token: &str) -> Result<Self, String> {
+        let message = base64_decode(token).ok_or("challenge is not valid base64")?;
+        if message.len() < 48 || &message[..8] != SIGNATURE {
+            return Err("challenge is not an NTLM message".to_string());
+        }
+        if u32_at(&message, 8) != 2 {
+            return Err("expected an NTLM challenge message".to_string());
+        }
+        let target_info_len = u16::from_le_bytes([message[40], message[41]]) as usize;
+        let target_info_offset = u32_at(&message, 44) as usize;
+        let target_info = message
+            .get(target_info_offset..target_info_offset + target_info_len)
+            .ok_or("challenge target info is out of bounds")?
+            .to_vec();
+
+        Ok(Self {
+            server_challenge: message[24..32].try_into().unwrap_or_default(),
+            flags: u32_at(&message, 20),
+            target_info,
+        })
+    }
+
+    /// The server's `MsvAvTimestamp`, which the response must use when sent.
+    fn timestamp(&self) -> Option<u64> {
+        let mut rest = self.target_info.as_slice();
+        while rest.len() >= 4 {
+            let id = u16::from_le_bytes([rest[0], rest[1]]);
+            let len = u16::from_le_bytes([rest[2], rest[3]]) as usize;
+            let value = rest.get(4..4 + len)?;
+            match id {
+                AV_EOL => return None,
+                AV_TIMESTAMP => return Some(u64::from_le_bytes(value.try_into().ok()?)),
+                _ => rest = &rest[4 + len..],
+            }
+        }
+        None
+    }
+}
+
+/// The final message of the handshake, answering `challenge` with an NTLMv2
+/// response, ready for an `Authorization` header.
+pub fn authenticate_message(credentials: &NtlmCredentials, challenge: &Challenge) -> String {
+    let client_challenge: [u8; 8] = rand::random();
+    let timestamp = challenge.timestamp().unwrap_or_else(|| {
+        let unix = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        (unix.as_secs() + FILETIME_EPOCH_OFFSET) * 10_000_000 + u64::from(unix.subsec_nanos() / 100)
+    });
+    let (lm_response, nt_response) =
+        ntlmv2_responses(credentials, challenge, client_challenge, timestamp);
+
+    let domain = utf16le(&credentials.domain);
+    let user = utf16le(&credentials.user);
+    let fields: [&[u8]; 6] = [&lm_response, &nt_response, &domain, &user, &[], &[]];
+
+    const HEADER_LEN: usize = 64;
+    let mut message =
+        Vec::with_capacity(HEADER_LEN + fields.iter().map(|f| f.len()).sum::<usize>());
+    message.extend_from_slice(SIGNATURE);
+    message.extend_from_slice(&3u32.to_le_bytes());
+    // Security buffers for the LM and NT responses, domain, user,
+    // workstation and session key, each pointing into the payload
+    let mut offset = HEADER_LEN;
+    for field in fields {
+        message.extend_from_slice(&(field.len() as u16).to_le_bytes());
+        message.extend_from_slice(&(field.len() as u16).to_le_bytes());
+        message.extend_from_slice(&(offset as u32).to_le_bytes());
+        offset += field.len();
+    }
+    message.extend_from_slice(&(challenge.flags & NEGOTIATE_FLAGS).to_le_bytes());
+    for field in fields {
+        message.extend_from_slice(field);
+    }
+    base64_encode(&message)
+}
+
+/// The LMv2 and NTLMv2 responses to `challenge` (MS-NLMP 3.3.2).
+fn ntlmv2_responses(
+    credentials: &NtlmCredentials,
+    challenge: &Challenge,
+    client_challenge: [u8; 8],
+    timestamp: u64,
+) -> (Vec<u8>, Vec<u8>) {
+    let key = ntowfv2(credentials);
+
+    let mut blob = vec![1, 1, 0, 0, 0, 0, 0, 0];
+    blob.extend_from_slice(&timestamp.to_le_bytes());
+    blob.extend_from_slice(&client_challenge);
+    blob.extend_from_slice(&[0; 4]);
+    blob.extend_from_slice(&challenge.target_info);
+    blob.extend_from_slice(&[0; 4]);
+
+    let mut nt_response =
+        hmac_md5(&key, &[&challenge.server_challenge[..], &blob].concat()).to_vec();
+    nt_response.extend_from_slice(&blob);
+
+    let mut lm_response = hmac_md5(
+        &key,
+        &[&challenge.server_challenge[..], &client_challenge].concat(),
+    )
+    .to_vec();
+    lm_response.extend_from_slice(&client_challenge);
+
+    (lm_response, nt_response)
+}
+
+/// The NTLMv2 response key: HMAC-MD5 of the upper-cased user and the domain,
+/// keyed with the MD4 hash of the password.
+fn ntowfv2(credentials: &NtlmCredentials) -> [u8; 16] {
+    let nt_hash = md4(&utf16le(&credentials.password));
+    let identity = format!("{}{}", credentials.user.to_uppercase(), credentials.domain);
+    hmac_md5(&nt_hash, &utf16le(&identity))
+}
+
+fn utf16le(value: &str) -> Vec<u8> {
+    value.encode_utf16().flat_map(u16::to_le_bytes).collect()
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+/// Standard base64, padding optional; `None` for anything else.
+fn base64_decode(input: &str) -> Option<Vec<u8>> {
+    let value = |c: u8| match c {
+        b'A'..=b'Z' => Some(c - b'A'),
+        b'a'..=b'z' => Some(c - b'a' + 26),
+        b'0'..=b'9' => Some(c - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    };
+    let input = input.trim().trim_end_matches('=').as_bytes();
+    let mut output = Vec::with_capacity(input.len() * 3 / 4);
+    for chunk in input.chunks(4) {
+        let mut triple = 0u32;
+        for (i, &c) in chunk.iter().enumerate() {
+            triple |= u32::from(value(c)?) << (18 - 6 * i);
+        }
+        let bytes = triple.to_be_bytes();
+        match chunk.len() {
+            4 => output.extend_from_slice(&bytes[1..4]),
+            3 => output.extend_from_slice(&bytes[1..3]),
+            2 => output.push(bytes[1]),
+            _ => return None,
+        }
+    }
+    Some(output)
+}
+
+fn hmac_md5(key: &[u8], data: &[u8]) -> [u8; 16] {
+    const BLOCK: usize = 64;
+    let mut block_key = [0u8; BLOCK];
+    if key.len() > BLOCK {
+        block_key[..16].copy_from_slice(&md5(key));
+    } else {
+        block_key[..key.len()].copy_from_slice(key);
+    }
+    let inner_key: Vec<u8> = block_key.iter().map(|b| b ^ 0x36).collect();
+    let outer_key: Vec<u8> = block_key.iter().map(|b| b ^ 0x5c).collect();
+    let inner = md5(&[&inner_key[..], data].concat());
+    md5(&[&outer_key[..], &inner].concat())
+}
+
+/// Pad a message into 64-byte blocks the way MD4 and MD5 both do.
+fn md_blocks(data: &[u8]) -> Vec<[u32; 16]> {
+    let mut padded = data.to_vec();
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0);
+    }
+    padded.extend_from_slice(&((data.len() as u64).wrapping_mul(8)).to_le_bytes());
+    padded
+        .chunks(64)
+        .map(|block| std::array::from_fn(|i| u32_at(block, i * 4)))
+        .collect()
+}
+
+fn digest(state: [u32; 4]) -> [u8; 16] {
+    let mut out = [0u8; 16];
+    for (i, word) in state.iter().enumerate() {
+        out[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+    }
+    out
+}
+
+/// MD4 (RFC 1320), which NTLM still uses for the password hash.
+fn md4(data: &[u8]) -> [u8; 16] {
+    let mut state: [u32; 4] = [0x6745_2301, 0xefcd_ab89, 0x98ba_dcfe, 0x1032_5476];
+    for x in md_blocks(data) {
+        let [mut a, mut b, mut c, mut d] = state;
+        let f = |x: u32, y: u32, z: u32| (x & y) | (!x & z);
+        let g = |x: u32, y: u32, z: u32| (x & y) | (x & z) | (y & z);
+        let h = |x: u32, y: u32, z: u32| x ^ y ^ z;
+
+        for &i in &[0, 4, 8, 12] {
+            a = a.wrapping_add(f(b, c, d)).wrapping_add(x[i]).rotate_left(3);
+            d = d
+                .wrapping_add(f(a, b, c))
+                .wrapping_add(x[i + 1])
+                .rotate_left(7);
+            c = c
+                .wrapping_add(f(d, a, b))
+                .wrapping_add(x[i + 2])
+                .rotate_left(11);
+            b = b
+                .wrapping_add(f(c, d, a))
+                .wrapping_add(x[i + 3])
+                .rotate_left(19);
+        }
+        for &i in &[0, 1, 2, 3] {
+            let k = 0x5a82_7999u32;
+            a = a
+                .wrapping_add(g(b, c, d))
+                .wrapping_add(x[i])
+                .wrapping_add(k)
+                .rotate_left(3);
+            d = d
+                .wrapping_add(g(a, b, c))
+                .wrapping_add(x[i + 4])
+                .wrapping_add(k)
+                .rotate_left(5);
+            c = c
+                .wrapping_add(g(d, a, b))
+                .wrapping_add(x[i + 8])
+                .wrapping_add(k)
+                .rotate_left(9);
+            b = b
+                .wrapping_add(g(c, d, a))
+                .wrapping_add(x[i + 12])
+                .wrapping_add(k)
+                .rotate_left(13);
+        }
+        for &i in &[0, 2, 1, 3] {
+            let k = 0x6ed9_eba1u32;
+            a = a
+                .wrapping_add(h(b, c, d))
+                .wrapping_add(x[i])
+                .wrapping_add(k)
+                .rotate_left(3);
+            d = d
+                .wrapping_add(h(a, b, c))
+                .wrapping_add(x[i + 8])
+                .wrapping_add(k)
+                .rotate_left(9);
+            c = c
+                .wrapping_add(h(d, a, b))
+                .wrapping_add(x[i + 4])
+                .wrapping_add(k)
+                .rotate_left(11);
+            b = b
+                .wrapping_add(h(c, d, a))
+                .wrapping_add(x[i + 12])
+                .wrapping_add(k)
+                .rotate_left(15);
+        }
+
+        for (word, value) in state.iter_mut().zip([a, b, c, d]) {
+            *word = word.wrapping_add(value);
+        }
+    }
+    digest(state)
+}
+
+/// MD5 (RFC 1321), for HMAC-MD5.
+fn md5(data: &[u8]) -> [u8; 16] {
+    const SHIFTS: [u32; 64] = [
+        7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 5, 9, 14, 20, 5, 9, 14, 20, 5,
+        9, 14, 20, 5, 9, 14, 20, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 6, 10,
+        15, 21, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21,
+    ];
+    // floor(abs(sin(i + 1)) * 2^32)
+    let constants: [u32; 64] =
+        std::array::from_fn(|i| ((i as f64 + 1.0).sin().abs() * 4_294_967_296.0) as u32);
+
+    let mut state: [u32; 4] = [0x6745_2301, 0xefcd_ab89, 0x98ba_dcfe, 0x1032_5476];
+    for x in md_blocks(data) {
+        let [mut a, mut b, mut c, mut d] = state;
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let rotated = a
+                .wrapping_add(f)
+                .wrapping_add(constants[i])
+                .wrapping_add(x[g])
+                .rotate_left(SHIFTS[i]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(rotated);
+        }
+        for (word, value) in state.iter_mut().zip([a, b, c, d]) {
+            *word = word.wrapping_add(value);
+        }
+    }
+    digest(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn test_digests() {
+        assert_eq!(hex(&md4(b"")), "31d6cfe0d16ae931b73c59d7e0c089c0");
+        assert_eq!(hex(&md4(b"abc")), "a448017aaf21d8525fc10ae87aa6729d");
+        assert_eq!(hex(&md5(b"")), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(
+            hex(&md5(b"The quick brown fox jumps over the lazy dog")),
+            "9e107d9d372bb6826bd81d3542a419d6"
+        );
+        assert_eq!(
+            hex(&hmac_md5(
+                b"key",
+                b"The quick brown fox jumps over the lazy dog"
+            )),
+            "80070713463e7749b90c2dc24911e275"
+        );
+    }
+
+    // Test vectors from MS-NLMP section 4.2.4
+    fn spec_credentials() -> NtlmCredentials {
+        NtlmCredentials::parse("Domain\\User:Password").unwrap()
+    }
+
+    #[test]
+    fn test_ntlmv2_matches_spec() {
+        let credentials = spec_credentials();
+        assert_eq!(
+            hex(&md4(&utf16le("Password"))),
+            "a4f49c406510bdcab6824ee7c30fd852"
+        );
+        assert_eq!(
+            hex(&ntowfv2(&credentials)),
+            "0c868a403bfd7a93a3001ef22ef02e3f"
+        );
+
+        let mut target_info = vec![0x02, 0x00, 0x0c, 0x00];
+        target_info.extend(utf16le("Domain"));
+        target_info.extend([0x01, 0x00, 0x0c, 0x00]);
+        target_info.extend(utf16le("Server"));
+        target_info.extend([0x00, 0x00, 0x00, 0x00]);
+        let challenge = Challenge {
+            server_challenge: [0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef],
+            flags: NEGOTIATE_FLAGS,
+            target_info,
+        };
+        let (lm_response, nt_response) = ntlmv2_responses(&credentials, &challenge, [0xaa; 8], 0);
+        assert_eq!(
+            hex(&lm_response),
+            "86c35097ac9cec102554764a57cccc19aaaaaaaaaaaaaaaa"
+        );
+        assert_eq!(hex(&nt_response[..16]), "68cd0ab851e51c96aabc927bebef6a1c");
+    }
+
+    #[test]
+    fn test_parse_credentials() {
+        let credentials = spec_credentials();
+        assert_eq!(credentials.domain, "Domain");
+        assert_eq!(credentials.user, "User");
+
+        let local = NtlmCredentials::parse("svc:p:w").unwrap();
+        assert_eq!((local.domain.as_str(), local.user.as_str()), ("", "svc"));
+        assert_eq!(local.password, "p:w");
+
+        assert!(NtlmCredentials::parse("no-password").is_err());
+        assert!(NtlmCredentials::parse("DOMAIN\\:pw").is_err());
+    }
+
+    #[test]
+    fn test_challenge_round_trip() {
+        let mut message = SIGNATURE.to_vec();
+        message.extend(2u32.to_le_bytes());
+        message.extend([0; 8]);
+        message.extend(NEGOTIATE_FLAGS.to_le_bytes());
+        message.extend([1, 2, 3, 4, 5, 6, 7, 8]);
+        message.extend([0; 8]);
+        // Target info: a timestamp pair and the terminator, at offset 48
+        let mut target_info = vec![0x07, 0x00, 0x08, 0x00];
+        target_info.extend(42u64.to_le_bytes());
+        target_info.extend([0; 4]);
+        message.extend((target_info.len() as u16).to_le_bytes());
+        message.extend((target_info.len() as u16).to_le_bytes());
+        message.extend(48u32.to_le_bytes());
+        message.extend(&target_info);
+
+        let challenge = Challenge::parse(&base64_encode(&message)).unwrap();
+        assert_eq!(challenge.server_challenge, [1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(challenge.timestamp(), Some(42));
+
+        assert!(Challenge::parse(&negotiate_message()).is_err());
+        assert!(Challenge::parse("not base64!").is_err());
+
+        let authenticate =
+            base64_decode(&authenticate_message(&spec_credentials(), &challenge)).unwrap();
+        assert_eq!(&authenticate[..8], SIGNATURE);
+        assert_eq!(u32_at(&authenticate, 8), 3);
+    }
+
+    #[test]
+    fn test_offered_scheme() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(offered_scheme(&headers), None);
+
+        headers.append(WWW_AUTHENTICATE, HeaderValue::from_static("Negotiate"));
+        assert_eq!(offered_scheme(&headers), Some("Negotiate"));
+        headers.append(WWW_AUTHENTICATE, HeaderValue::from_static("NTLM"));
+        assert_eq!(offered_scheme(&headers), Some("NTLM"));
+
+        let mut challenge = HeaderMap::new();
+        challenge.insert(
+            WWW_AUTHENTICATE,
+            HeaderValue::from_static("NTLM TlRMTVNTUAACAAAA"),
+        );
+        assert_eq!(
+            challenge_token(&challenge, "NTLM"),
+            Some("TlRMTVNTUAACAAAA")
+        );
+        assert_eq!(challenge_token(&challenge, "Negotiate"), None);
+    }
+
+    #[test]
+    fn test_base64_decode() {
+        assert_eq!(base64_decode("dXNlcjpwdw=="), Some(b"user:pw".to_vec()));
+        assert_eq!(base64_decode("Zm9v"), Some(b"foo".to_vec()));
+        assert_eq!(base64_decode("Zg"), Some(b"f".to_vec()));
+        assert_eq!(base64_decode("Z"), None);
+    }
+}
@@ -0,0 +1,72 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// Clears from the cursor to the end of the terminal line.
+const CLEAR_LINE: &str = "\x1b[K";
+
+/// Whether human-readable output goes to stderr. With --output json stdout
+/// carries only the JSON document, so it stays parseable.
+static TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// The status line drawn in place on the terminal's last line, if any. Lines
+/// logged meanwhile are printed above it, so it's redrawn after each one.
+static STATUS_LINE: Mutex<Option<String>> = Mutex::new(None);
+
+/// Send every later [`logln!`] line to stderr instead of stdout.
+pub fn redirect_to_stderr() {
+    TO_STDERR.store(true, Ordering::Relaxed);
+}
+
+pub fn to_stderr() -> bool {
+    TO_STDERR.load(Ordering::Relaxed)
+}
+
+/// Draw `line` as the status line, overwriting the previous one. Only for a
+/// terminal on stdout, since it relies on carriage returns.
+pub fn show_status(line: &str) {
+    let mut status = STATUS_LINE.lock().unwrap_or_else(|e| e.into_inner());
+    let mut stdout = io::stdout().lock();
+    let _ = write!(stdout, "\r{}{}", line, CLEAR_LINE);
+    let _ = stdout.flush();
+    *status = Some(line.to_string());
+}
+
+/// Leave the status line on screen as it last was and move below it.
+pub fn end_status() {
+    let mut status = STATUS_LINE.lock().unwrap_or_else(|e| e.into_inner());
+    if status.take().is_some() {
+        println!();
+    }
+}
+
+/// Print one line for [`logln!`], above the status line if one is shown.
+pub fn print_line(args: fmt::Arguments) {
+    if to_stderr() {
+        eprintln!("{}", args);
+        return;
+    }
+    let status = STATUS_LINE.lock().unwrap_or_else(|e| e.into_inner());
+    match &*status {
+        Some(line) => {
+            let mut stdout = io::stdout().lock();
+            let _ = write!(stdout, "\r{}{}\n{}", CLEAR_LINE, args, line);
+            let _ = stdout.flush();
+        }
+        None => println!("{}", args),
+    }
+}
+
+/// `println!` for human-readable output: progress, per-request lines and
+/// reports. It goes to stdout unless [`redirect_to_stderr`] was called.
+macro_rules! logln {
+    () => {
+        $crate::output::print_line(format_args!(""))
+    };
+    ($($arg:tt)*) => {
+        $crate::output::print_line(format_args!($($arg)*))
+    };
+}
+
+pub(crate) use logln;
@@ -0,0 +1,77 @@
+use std::time::Duration;
+
+/// Parse a human-friendly duration such as `250ms`, `1.5s`, `2m` or `1h`.
+/// A bare number is taken as seconds.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("Invalid duration: {}", value))?;
+    let seconds = match unit.trim() {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        _ => {
+            return Err(format!(
+                "Invalid duration unit in {} (use ms, s, m or h)",
+                value
+            ))
+        }
+    };
+
+    Duration::try_from_secs_f64(seconds).map_err(|_| format!("Invalid duration: {}", value))
+}
+
+/// Encode `key=value` entries as an `application/x-www-form-urlencoded` body.
+pub fn encode_form(entries: &[String]) -> Result<String, String> {
+    let mut serializer = url::form_urlencoded::Serializer::new(String::new());
+    for entry in entries {
+        let (key, value) = entry
+            .split_once('=')
+            .ok_or_else(|| format!("Invalid form field '{}': expected key=value", entry))?;
+        serializer.append_pair(key, value);
+    }
+    Ok(serializer.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration_units() {
+        assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_duration("1.5s"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("2m"), Ok(Duration::from_secs(120)));
+        assert_eq!(parse_duration("1h"), Ok(Duration::from_secs(3600)));
+        assert_eq!(parse_duration("3"), Ok(Duration::from_secs(3)));
+    }
+
+    #[test]
+    fn test_parse_duration_invalid() {
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("ms").is_err());
+        assert!(parse_duration("5 days").is_err());
+        assert!(parse_duration("1.2.3s").is_err());
+    }
+
+    #[test]
+    fn test_encode_form() {
+        let entries = vec![
+            "user=jane doe".to_string(),
+            "note=a&b=c".to_string(),
+            "empty=".to_string(),
+        ];
+        assert_eq!(
+            encode_form(&entries),
+            Ok("user=jane+doe&note=a%26b%3Dc&empty=".to_string())
+        );
+        assert!(encode_form(&["novalue".to_string()]).is_err());
+    }
+}
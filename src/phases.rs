@@ -0,0 +1,194 @@
+use reqwest::Method;
+use serde::Deserialize;
+use std::fs;
+use std::time::Duration;
+
+use crate::parse::parse_duration;
+
+/// The phases of a single run, executed in order, read from a TOML file:
+///
+/// ```toml
+/// [[phase]]
+/// name = "warm-up"
+/// duration = "30s"
+/// concurrency = 10
+///
+/// [[phase]]
+/// duration = "60s"
+/// concurrency = 50
+/// method = "POST"
+/// body = '{"item": 1}'
+///
+/// [[phase]]
+/// name = "ramp-down"
+/// duration = "30s"
+/// concurrency = 50
+/// ramp_to = 0
+/// ```
+///
+/// Settings a phase leaves out come from the command line or config file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PhaseFile {
+    #[serde(rename = "phase", default)]
+    phases: Vec<PhaseSpec>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PhaseSpec {
+    name: Option<String>,
+    duration: DurationSpec,
+    concurrency: Option<u32>,
+    /// Concurrency at the end of the phase, reached linearly from `concurrency`
+    ramp_to: Option<u32>,
+    /// Requests per second across all workers, paced like --gap
+    rate: Option<f64>,
+    method: Option<String>,
+    body: Option<String>,
+}
+
+/// A phase duration, as seconds or a string such as `90s` or `5m`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum DurationSpec {
+    Seconds(u64),
+    Text(String),
+}
+
+/// A validated phase, ready to shape one part of the run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Phase {
+    pub name: String,
+    pub duration: Duration,
+    pub concurrency: Option<u32>,
+    pub ramp_to: Option<u32>,
+    pub rate: Option<f64>,
+    pub method: Option<Method>,
+    pub body: Option<String>,
+}
+
+impl Phase {
+    /// Start-to-start gap for each of `concurrency` workers that together
+    /// send `rate` requests per second, when the phase sets a rate.
+    pub fn gap(&self, concurrency: u32) -> Option<Duration> {
+        self.rate
+            .map(|rate| Duration::from_secs_f64(concurrency as f64 / rate))
+    }
+}
+
+/// Read and validate a phases file.
+pub fn load_phases(path: &str) -> Result<Vec<Phase>, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    parse_phases(&content).map_err(|e| format!("{}: {}", path, e))
+}
+
+fn parse_phases(content: &str) -> Result<Vec<Phase>, String> {
+    let file: PhaseFile = toml::from_str(content).map_err(|e| e.to_string())?;
+    if file.phases.is_empty() {
+        return Err("Phases file must define at least one [[phase]]".to_string());
+    }
+
+    file.phases
+        .into_iter()
+        .enumerate()
+        .map(|(index, spec)| resolve(index, spec))
+        .collect()
+}
+
+fn resolve(index: usize, spec: PhaseSpec) -> Result<Phase, String> {
+    let name = spec.name.unwrap_or_else(|| (index + 1).to_string());
+    let duration = match spec.duration {
+        DurationSpec::Seconds(seconds) => Duration::from_secs(seconds),
+        DurationSpec::Text(text) => parse_duration(&text)?,
+    };
+    if duration.is_zero() {
+        return Err(format!("Duration must be > 0 in phase '{}'", name));
+    }
+    if spec.concurrency == Some(0) {
+        return Err(format!("Concurrency must be > 0 in phase '{}'", name));
+    }
+    if let Some(rate) = spec.rate {
+        if !(rate > 0.0 && rate.is_finite()) {
+            return Err(format!("Rate must be > 0 in phase '{}'", name));
+        }
+        if spec.ramp_to.is_some() {
+            return Err(format!("Phase '{}' can't set both rate and ramp_to", name));
+        }
+    }
+    let method = match &spec.method {
+        Some(method) => Some(
+            Method::from_bytes(method.trim().to_uppercase().as_bytes())
+                .map_err(|_| format!("Invalid HTTP method in phase '{}': {}", name, method))?,
+        ),
+        None => None,
+    };
+
+    Ok(Phase {
+        name,
+        duration,
+        concurrency: spec.concurrency,
+        ramp_to: spec.ramp_to,
+        rate: spec.rate,
+        method,
+        body: spec.body,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_phases() {
+        let phases = parse_phases(
+            r#"
+            [[phase]]
+            name = "warm-up"
+            duration = 30
+            concurrency = 10
+
+            [[phase]]
+            duration = "1m"
+            method = "post"
+            body = '{"item": 1}'
+            rate = 200
+
+            [[phase]]
+            name = "ramp-down"
+            duration = "30s"
+            concurrency = 50
+            ramp_to = 0
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(phases.len(), 3);
+        assert_eq!(phases[0].name, "warm-up");
+        assert_eq!(phases[0].duration, Duration::from_secs(30));
+        assert_eq!(phases[1].name, "2");
+        assert_eq!(phases[1].duration, Duration::from_secs(60));
+        assert_eq!(phases[1].method, Some(Method::POST));
+        assert_eq!(phases[1].concurrency, None);
+        assert_eq!(phases[1].gap(10), Some(Duration::from_millis(50)));
+        assert_eq!(phases[2].ramp_to, Some(0));
+        assert_eq!(phases[2].gap(50), None);
+    }
+
+    #[test]
+    fn test_parse_phases_rejects_bad_phases() {
+        assert!(parse_phases("").is_err());
+        assert!(parse_phases("[[phase]]\nconcurrency = 5").is_err());
+        assert!(parse_phases("[[phase]]\nduration = 5\nworkers = 5").is_err());
+        assert_eq!(
+            parse_phases("[[phase]]\nduration = 0").unwrap_err(),
+            "Duration must be > 0 in phase '1'"
+        );
+        assert_eq!(
+            parse_phases("[[phase]]\nname = \"x\"\nduration = 5\nrate = 10.0\nramp_to = 1")
+                .unwrap_err(),
+            "Phase 'x' can't set both rate and ramp_to"
+        );
+        assert!(parse_phases("[[phase]]\nduration = 5\nmethod = \"G E T\"").is_err());
+    }
+}
@@ -0,0 +1,232 @@
+use std::time::{Duration, Instant};
+use url::Url;
+
+use crate::scenario::Target;
+
+/// How long the calibration request may take before it is given up on.
+const CALIBRATION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A timeout below this multiple of the calibration latency risks spurious
+/// failures once the target is under load.
+pub const TIMEOUT_SAFETY_FACTOR: u32 = 10;
+
+/// Send one request to `target` and measure how long the response takes,
+/// regardless of the configured timeout. Any status counts as a response.
+pub async fn calibrate(
+    client: &reqwest::Client,
+    target: &Target,
+    authorization: Option<&str>,
+) -> Result<Duration, reqwest::Error> {
+    let request = probe_request(client, target, authorization).timeout(CALIBRATION_TIMEOUT);
+
+    let start = Instant::now();
+    request.send().await?;
+    Ok(start.elapsed())
+}
+
+/// Send one request to `target` and read its whole body, for the size of a
+/// representative response. Any status counts as a response.
+pub async fn probe_response_size(
+    client: &reqwest::Client,
+    target: &Target,
+    authorization: Option<&str>,
+) -> Result<u64, reqwest::Error> {
+    let response = probe_request(client, target, authorization)
+        .timeout(CALIBRATION_TIMEOUT)
+        .send()
+        .await?;
+    Ok(response.bytes().await?.len() as u64)
+}
+
+/// Requests a run of `duration` is expected to send: a batch of
+/// `concurrency` every second, ramping linearly to `ramp_to` when set, or
+/// one request per `gap` on each of `concurrency` persistent workers. A
+/// `ramp_up` from a single worker leaves out the requests not yet started.
+pub fn expected_requests(
+    concurrency: u32,
+    ramp_to: Option<u32>,
+    ramp_up: Duration,
+    duration: Duration,
+    gap: Option<Duration>,
+) -> u64 {
+    let per_worker = gap.map_or(1.0, |gap| 1.0 / gap.as_secs_f64());
+    let per_second = match gap {
+        Some(_) => concurrency as f64 * per_worker,
+        None => (concurrency as f64 + ramp_to.unwrap_or(concurrency) as f64) / 2.0,
+    };
+    // Over the ramp-up, concurrency averages halfway between 1 and full
+    let ramp_shortfall =
+        concurrency.saturating_sub(1) as f64 / 2.0 * per_worker * ramp_up.as_secs_f64();
+    (per_second * duration.as_secs_f64() - ramp_shortfall)
+        .max(0.0)
+        .round() as u64
+}
+
+/// Open up to `connections` pooled connections before the measured run by
+/// sending that many requests to `target` at once, spread over `clients`.
+/// Returns how many succeeded; none of them count towards the results.
+pub async fn prewarm(
+    clients: &[reqwest::Client],
+    target: &Target,
+    authorization: Option<&str>,
+    connections: u32,
+) -> u32 {
+    let requests: Vec<_> = (0..connections as usize)
+        .map(|i| {
+            let request = probe_request(&clients[i % clients.len()], target, authorization)
+                .timeout(target.timeout);
+            tokio::spawn(async move {
+                // Read the body so the connection goes back to the pool
+                let response = request.send().await?;
+                response.bytes().await
+            })
+        })
+        .collect();
+
+    let mut warmed = 0;
+    for request in requests {
+        if let Ok(Ok(_)) = request.await {
+            warmed += 1;
+        }
+    }
+    warmed
+}
+
+/// The first target for each distinct scheme, host and port, which are the
+/// ones a pre-warm needs to reach so that no host starts the run cold.
+/// Targets whose URL isn't known before the run are left out.
+pub fn warm_targets(targets: &[Target]) -> Vec<&Target> {
+    let mut origins = Vec::new();
+    let mut warm = Vec::new();
+    for target in targets {
+        let Ok(url) = Url::parse(&target.url) else {
+            continue;
+        };
+        let origin = url.origin();
+        if !origins.contains(&origin) {
+            origins.push(origin);
+            warm.push(target);
+        }
+    }
+    warm
+}
+
+/// A request to `target` with its method and headers but no body.
+fn probe_request(
+    client: &reqwest::Client,
+    target: &Target,
+    authorization: Option<&str>,
+) -> reqwest::RequestBuilder {
+    let method = target.method.clone().unwrap_or(reqwest::Method::GET);
+    let mut request = client.request(method, &target.url);
+    for (name, value) in &target.headers {
+        request = request.header(name, value);
+    }
+    if let Some(authorization) = authorization {
+        request = request.header(reqwest::header::AUTHORIZATION, authorization);
+    }
+    request
+}
+
+/// The smallest whole-second timeout that leaves `TIMEOUT_SAFETY_FACTOR`
+/// times the calibration latency.
+pub fn safe_timeout_secs(latency: Duration) -> u64 {
+    let safe = latency * TIMEOUT_SAFETY_FACTOR;
+    (safe.as_secs_f64().ceil() as u64).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_safe_timeout_secs() {
+        assert_eq!(safe_timeout_secs(Duration::from_millis(20)), 1);
+        assert_eq!(safe_timeout_secs(Duration::from_millis(100)), 1);
+        assert_eq!(safe_timeout_secs(Duration::from_millis(250)), 3);
+        assert_eq!(safe_timeout_secs(Duration::from_secs(2)), 20);
+    }
+
+    #[test]
+    fn test_expected_requests() {
+        let minute = Duration::from_secs(60);
+        let none = Duration::ZERO;
+        assert_eq!(expected_requests(50, None, none, minute, None), 3000);
+        assert_eq!(expected_requests(10, Some(30), none, minute, None), 1200);
+        assert_eq!(expected_requests(0, Some(10), none, minute, None), 300);
+        assert_eq!(
+            expected_requests(4, None, none, minute, Some(Duration::from_millis(50))),
+            4800
+        );
+        // 21 workers ramped up over 10s average 11 during it
+        let ramp_up = Duration::from_secs(10);
+        assert_eq!(expected_requests(21, None, ramp_up, minute, None), 1160);
+        assert_eq!(
+            expected_requests(21, None, ramp_up, minute, Some(Duration::from_millis(500))),
+            2320
+        );
+    }
+
+    #[tokio::test]
+    async fn test_probe_response_size() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/item")
+            .with_status(404)
+            .with_body("x".repeat(1234))
+            .create_async()
+            .await;
+        let target = Target::new(&format!("{}/item", server.url()), Duration::from_secs(5));
+
+        let size = probe_response_size(&reqwest::Client::new(), &target, None)
+            .await
+            .unwrap();
+
+        assert_eq!(size, 1234);
+    }
+
+    #[test]
+    fn test_warm_targets_picks_one_per_host() {
+        let timeout = Duration::from_secs(1);
+        let targets = [
+            Target::new("https://a.example.com/one", timeout),
+            Target::new("https://a.example.com/two", timeout),
+            Target::new("https://b.example.com/", timeout),
+            Target::new("https://a.example.com:8443/", timeout),
+            Target::new("http://a.example.com/", timeout),
+            Target::new("{captured.next}", timeout),
+        ];
+
+        let urls: Vec<&str> = warm_targets(&targets)
+            .iter()
+            .map(|target| target.url.as_str())
+            .collect();
+
+        assert_eq!(
+            urls,
+            [
+                "https://a.example.com/one",
+                "https://b.example.com/",
+                "https://a.example.com:8443/",
+                "http://a.example.com/"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_prewarm_counts_successful_requests() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/warm")
+            .with_body("ok")
+            .expect(3)
+            .create_async()
+            .await;
+        let target = Target::new(&format!("{}/warm", server.url()), Duration::from_secs(5));
+
+        let warmed = prewarm(&[reqwest::Client::new()], &target, None, 3).await;
+
+        assert_eq!(warmed, 3);
+        mock.assert_async().await;
+    }
+}
@@ -0,0 +1,95 @@
+use reqwest::Method;
+
+/// One request as the run would send it, for --preview-request.
+#[derive(Debug, Clone)]
+pub struct PreviewRequest {
+    pub method: Method,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<String>,
+    /// curl options that reproduce client settings, e.g. `("--max-time", Some("5"))`
+    pub options: Vec<(String, Option<String>)>,
+}
+
+impl PreviewRequest {
+    /// The request as a curl command, one option per line. Values are printed
+    /// as given, so secrets must already be masked.
+    pub fn to_curl(&self) -> String {
+        let mut lines = vec![format!(
+            "curl -X {} {}",
+            self.method,
+            shell_quote(&self.url)
+        )];
+        for (flag, value) in &self.options {
+            match value {
+                Some(value) => lines.push(format!("  {} {}", flag, shell_quote(value))),
+                None => lines.push(format!("  {}", flag)),
+            }
+        }
+        for (name, value) in &self.headers {
+            lines.push(format!(
+                "  -H {}",
+                shell_quote(&format!("{}: {}", name, value))
+            ));
+        }
+        if let Some(body) = &self.body {
+            lines.push(format!("  --data-raw {}", shell_quote(body)));
+        }
+        lines.join(" \\\n")
+    }
+}
+
+/// Quote `value` for a POSIX shell. Plain words are left as they are.
+fn shell_quote(value: &str) -> String {
+    let plain = !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:@%+=,".contains(c));
+    if plain {
+        value.to_string()
+    } else {
+        format!("'{}'", value.replace('\'', "'\\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_curl() {
+        let request = PreviewRequest {
+            method: Method::POST,
+            url: "https://api.example.com/orders?page=1&size=10".to_string(),
+            headers: vec![
+                ("Content-Type".to_string(), "application/json".to_string()),
+                ("Authorization".to_string(), "<redacted>".to_string()),
+            ],
+            body: Some(r#"{"note": "it's fine"}"#.to_string()),
+            options: vec![
+                ("--max-time".to_string(), Some("5".to_string())),
+                ("--http1.1".to_string(), None),
+            ],
+        };
+
+        assert_eq!(
+            request.to_curl(),
+            "curl -X POST 'https://api.example.com/orders?page=1&size=10' \\\n  \
+             --max-time 5 \\\n  \
+             --http1.1 \\\n  \
+             -H 'Content-Type: application/json' \\\n  \
+             -H 'Authorization: <redacted>' \\\n  \
+             --data-raw '{\"note\": \"it'\\''s fine\"}'"
+        );
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(
+            shell_quote("https://example.com/a"),
+            "https://example.com/a"
+        );
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(shell_quote("a b"), "'a b'");
+    }
+}
@@ -0,0 +1,375 @@
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::export::ExportWriter;
+use crate::requests::RequestMetrics;
+use crate::stats::LatencyRecorder;
+use crate::timeseries::Completion;
+
+/// Column header of a per-request CSV export. New columns are only ever
+/// appended, so existing columns keep their positions.
+pub const CSV_HEADER: &str =
+    "timestamp_ms,attempts,status,duration_ms,success,request_bytes,response_bytes";
+
+/// Header of exports from before the size columns, still accepted.
+const LEGACY_CSV_HEADER: &str = "timestamp_ms,attempts,status,duration_ms,success";
+
+/// One request as recorded in a per-request CSV export.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestRecord {
+    /// Wall-clock time the request started, in milliseconds since the Unix
+    /// epoch, for correlating with logs
+    pub timestamp_ms: u64,
+    /// The attempt count including retries
+    pub attempts: u32,
+    /// `None` when no response arrived
+    pub status: Option<u16>,
+    /// Latency measured with the monotonic clock
+    pub duration: Duration,
+    pub success: bool,
+    /// Request body size; `None` in legacy exports
+    pub request_bytes: Option<u64>,
+    /// Response body size as read, or its `Content-Length` when the body
+    /// wasn't read; `None` without either
+    pub response_bytes: Option<u64>,
+}
+
+impl RequestRecord {
+    /// The record as a CSV row in [`CSV_HEADER`] order, without a newline.
+    pub fn csv_row(&self) -> String {
+        let bytes = |size: Option<u64>| size.map_or(String::new(), |size| size.to_string());
+        format!(
+            "{},{},{},{},{},{},{}",
+            self.timestamp_ms,
+            self.attempts,
+            self.status
+                .map_or(String::new(), |status| status.to_string()),
+            self.duration.as_micros() as f64 / 1000.0,
+            self.success,
+            bytes(self.request_bytes),
+            bytes(self.response_bytes)
+        )
+    }
+}
+
+/// The per-request CSV file of a --csv run, appended to as requests finish.
+/// Shared by every worker; call [`RecordLog::finish`] once the run is over.
+pub struct RecordLog {
+    state: Mutex<LogState>,
+}
+
+struct LogState {
+    /// `None` once finished
+    writer: Option<ExportWriter>,
+    /// The first write error, reported by [`RecordLog::finish`] so a full
+    /// disk doesn't interrupt the run
+    error: Option<io::Error>,
+}
+
+impl RecordLog {
+    /// Create the file at `path` and write the header.
+    pub fn create(path: &str) -> io::Result<Self> {
+        let mut writer = ExportWriter::create(path)?;
+        writeln!(writer, "{}", CSV_HEADER)?;
+        Ok(Self {
+            state: Mutex::new(LogState {
+                writer: Some(writer),
+                error: None,
+            }),
+        })
+    }
+
+    /// Append one row. Rows written after [`RecordLog::finish`] are dropped.
+    pub fn write(&self, record: &RequestRecord) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let LogState { writer, error } = &mut *state;
+        if let (Some(writer), None) = (writer, &error) {
+            if let Err(e) = writeln!(writer, "{}", record.csv_row()) {
+                *error = Some(e);
+            }
+        }
+    }
+
+    /// Flush and close the file, returning the first error of the run.
+    pub fn finish(&self) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let finished = match state.writer.take() {
+            Some(writer) => writer.finish(),
+            None => Ok(()),
+        };
+        match state.error.take() {
+            Some(e) => Err(e),
+            None => finished,
+        }
+    }
+}
+
+/// Parse a per-request CSV export. The header must match [`CSV_HEADER`], or
+/// the legacy header without the size columns.
+pub fn parse_csv(content: &str) -> Result<Vec<RequestRecord>, String> {
+    let mut lines = content.lines().enumerate();
+    let sized = match lines.next() {
+        Some((_, header)) if header.trim() == CSV_HEADER => true,
+        Some((_, header)) if header.trim() == LEGACY_CSV_HEADER => false,
+        _ => return Err(format!("expected the header '{}'", CSV_HEADER)),
+    };
+
+    lines
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            parse_row(line, sized).map_err(|e| format!("line {}: {}", index + 1, e))
+        })
+        .collect()
+}
+
+/// An optional byte count, empty when unknown.
+fn parse_bytes(value: &str) -> Result<Option<u64>, String> {
+    match value {
+        "" => Ok(None),
+        value => value
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("invalid size '{}'", value)),
+    }
+}
+
+fn parse_row(line: &str, sized: bool) -> Result<RequestRecord, String> {
+    let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+    let expected = if sized { 7 } else { 5 };
+    if fields.len() != expected {
+        return Err(format!(
+            "expected {} fields, found {}",
+            expected,
+            fields.len()
+        ));
+    }
+    let [timestamp_ms, attempts, status, duration_ms, success] = fields[..5] else {
+        unreachable!("the field count was checked");
+    };
+    let (request_bytes, response_bytes) = if sized {
+        (parse_bytes(fields[5])?, parse_bytes(fields[6])?)
+    } else {
+        (None, None)
+    };
+
+    let duration_ms: f64 = duration_ms
+        .parse()
+        .map_err(|_| format!("invalid duration '{}'", duration_ms))?;
+    Ok(RequestRecord {
+        timestamp_ms: timestamp_ms
+            .parse()
+            .map_err(|_| format!("invalid timestamp '{}'", timestamp_ms))?,
+        attempts: attempts
+            .parse()
+            .map_err(|_| format!("invalid attempt count '{}'", attempts))?,
+        status: match status {
+            "" => None,
+            status => Some(
+                status
+                    .parse()
+                    .map_err(|_| format!("invalid status '{}'", status))?,
+            ),
+        },
+        duration: Duration::try_from_secs_f64(duration_ms / 1000.0)
+            .map_err(|_| format!("invalid duration '{}'", duration_ms))?,
+        success: success
+            .parse()
+            .map_err(|_| format!("invalid success flag '{}'", success))?,
+        request_bytes,
+        response_bytes,
+    })
+}
+
+/// Rebuild run metrics from recorded requests, as if they had just been
+/// sent. Returns the metrics and the span of time the records cover.
+pub async fn replay_metrics(
+    records: &[RequestRecord],
+    raw_latencies: bool,
+    max_samples: Option<usize>,
+    timeseries: bool,
+    apdex_threshold: Option<Duration>,
+    hard_deadline: Option<Duration>,
+) -> (RequestMetrics, Duration) {
+    let mut metrics = RequestMetrics::new(raw_latencies, max_samples, timeseries);
+    if let Some(threshold) = apdex_threshold {
+        metrics.track_apdex(threshold);
+    }
+    if let Some(deadline) = hard_deadline {
+        metrics.track_deadline(deadline);
+    }
+    let first_start = records.iter().map(|r| r.timestamp_ms).min().unwrap_or(0);
+    metrics.started_at = UNIX_EPOCH + Duration::from_millis(first_start);
+    let mut elapsed = Duration::ZERO;
+
+    for record in records {
+        let finished_at =
+            Duration::from_millis(record.timestamp_ms - first_start) + record.duration;
+        elapsed = elapsed.max(finished_at);
+
+        if record.success {
+            metrics.request_times.lock().await.record(record.duration);
+            *metrics.successful_requests.lock().await += 1;
+            *metrics
+                .success_retries
+                .lock()
+                .await
+                .entry(record.attempts.saturating_sub(1))
+                .or_insert(0) += 1;
+        } else {
+            metrics.failure_times.lock().await.record(record.duration);
+            *metrics.failed_requests.lock().await += 1;
+        }
+        if let Some(status) = record.status {
+            metrics
+                .status_latencies
+                .lock()
+                .await
+                .entry(status / 100)
+                .or_insert_with(|| LatencyRecorder::new(None))
+                .record(record.duration);
+            *metrics
+                .status_counts
+                .lock()
+                .await
+                .entry(status)
+                .or_insert(0) += 1;
+        }
+        metrics.record_apdex(record.duration, record.success).await;
+        metrics.record_deadline(record.duration).await;
+        if let Some(completions) = &metrics.completions {
+            completions.lock().await.push(Completion {
+                at: finished_at,
+                latency: record.duration,
+                success: record.success,
+            });
+        }
+    }
+
+    (metrics, elapsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    const SAMPLE: &str =
+        "timestamp_ms,attempts,status,duration_ms,success,request_bytes,response_bytes
+1700000000000,1,200,12.5,true,0,5120
+1700000000500,3,,1000,false,64,
+1700000001000,1,404,2,true,0,
+";
+
+    #[test]
+    fn test_parse_csv() {
+        let records = parse_csv(SAMPLE).unwrap();
+
+        assert_eq!(records.len(), 3);
+        assert_eq!(
+            records[1],
+            RequestRecord {
+                timestamp_ms: 1_700_000_000_500,
+                attempts: 3,
+                status: None,
+                duration: Duration::from_secs(1),
+                success: false,
+                request_bytes: Some(64),
+                response_bytes: None,
+            }
+        );
+        assert_eq!(records[0].duration, Duration::from_micros(12_500));
+        assert_eq!(records[0].response_bytes, Some(5120));
+    }
+
+    #[test]
+    fn test_record_log_round_trips() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("requests.csv.gz");
+        let path = path.to_str().unwrap();
+        let records = parse_csv(SAMPLE).unwrap();
+
+        let log = RecordLog::create(path).unwrap();
+        for record in &records {
+            log.write(record);
+        }
+        log.finish().unwrap();
+        // A late request after the file is closed is dropped, not an error
+        log.write(&records[0]);
+        log.finish().unwrap();
+
+        let content = crate::export::read_export(path).unwrap();
+        assert_eq!(
+            content.lines().nth(2),
+            Some("1700000000500,3,,1000,false,64,")
+        );
+        assert_eq!(parse_csv(&content).unwrap(), records);
+    }
+
+    #[test]
+    fn test_parse_legacy_csv() {
+        let records = parse_csv(&format!(
+            "{}\n1700000000000,1,200,12.5,true\n",
+            LEGACY_CSV_HEADER
+        ))
+        .unwrap();
+
+        assert_eq!(records[0].status, Some(200));
+        assert_eq!(records[0].request_bytes, None);
+        assert_eq!(records[0].response_bytes, None);
+        // Size columns only belong under the new header
+        assert!(parse_csv(&format!("{}\n1,1,200,2,true,0,0\n", LEGACY_CSV_HEADER)).is_err());
+    }
+
+    #[test]
+    fn test_parse_csv_rejects_bad_input() {
+        assert!(parse_csv("a,b,c\n").is_err());
+        let err = parse_csv(&format!("{}\n1,1,200,abc,true,0,0\n", CSV_HEADER)).unwrap_err();
+        assert!(err.starts_with("line 2:"), "{}", err);
+        let err = parse_csv(&format!("{}\n1,1,200,2,true,0,-1\n", CSV_HEADER)).unwrap_err();
+        assert_eq!(err, "line 2: invalid size '-1'");
+    }
+
+    #[tokio::test]
+    async fn test_replay_metrics() {
+        let records = parse_csv(SAMPLE).unwrap();
+
+        let (metrics, elapsed) = replay_metrics(
+            &records,
+            false,
+            None,
+            true,
+            Some(Duration::from_millis(10)),
+            Some(Duration::from_millis(10)),
+        )
+        .await;
+
+        assert_eq!(*metrics.successful_requests.lock().await, 2);
+        assert_eq!(*metrics.failed_requests.lock().await, 1);
+        assert_eq!(metrics.request_times.lock().await.count(), 2);
+        assert_eq!(metrics.failure_times.lock().await.count(), 1);
+        assert_eq!(elapsed, Duration::from_millis(1500));
+        let classes: Vec<u16> = metrics
+            .status_latencies
+            .lock()
+            .await
+            .keys()
+            .copied()
+            .collect();
+        assert_eq!(classes, vec![2, 4]);
+        assert_eq!(
+            *metrics.status_counts.lock().await,
+            BTreeMap::from([(200, 1), (404, 1)])
+        );
+        assert_eq!(
+            *metrics.success_retries.lock().await,
+            BTreeMap::from([(0, 2)])
+        );
+        // 12.5ms tolerating, 2ms satisfied, the failure frustrated
+        let apdex = *metrics.apdex.as_ref().unwrap().lock().await;
+        assert_eq!(apdex.score(), Some(0.5));
+        // The 12.5ms success and the 1s failure are over the 10ms deadline
+        let deadline = *metrics.deadline.as_ref().unwrap().lock().await;
+        assert_eq!((deadline.violations, deadline.requests), (2, 3));
+    }
+}
@@ -1,55 +1,1175 @@
 use colored::*;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use rand::Rng;
+use reqwest::header::{HeaderMap, CONTENT_LENGTH};
+use reqwest::{Method, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::sync::Mutex;
 use tokio::time::sleep;
+use tokio_rustls::rustls;
 
+use crate::auth::AuthRefresher;
+use crate::backpressure::Backpressure;
+use crate::bandwidth::ByteRateLimiter;
+use crate::chain::JourneyTimes;
+use crate::dns::DnsTimings;
+use crate::folded::FoldedLatencies;
+#[cfg(feature = "ntlm")]
+use crate::ntlm::{self, Challenge, NtlmCredentials};
+use crate::output::logln;
+use crate::records::{RecordLog, RequestRecord};
+use crate::redact::redact_url;
+use crate::scenario::Target;
+use crate::schema::ResponseSchema;
+use crate::servertiming::ServerTimings;
+use crate::stats::{
+    Apdex, DeadlineCheck, InFlightSamples, LatencyRecorder, LatencySamples, RateAccuracy,
+    SpawnStalls, UrlSamples, UrlStats,
+};
+use crate::timeseries::Completion;
+
+/// Distinct schema violation messages kept as examples for the report.
+const MAX_SCHEMA_SAMPLES: usize = 5;
+
+/// Distinct out-of-range body sizes kept as examples for the report.
+const MAX_SIZE_SAMPLES: usize = 5;
+
+/// Response counts keyed by status and body hash.
+pub type BodyHashes = BTreeMap<(u16, u64), u32>;
+
+#[derive(Clone)]
 pub struct RequestMetrics {
     pub successful_requests: Arc<tokio::sync::Mutex<u32>>,
     pub failed_requests: Arc<tokio::sync::Mutex<u32>>,
-    pub request_times: Arc<Mutex<Vec<Duration>>>,
+    /// Latencies of successful requests
+    pub request_times: Arc<Mutex<LatencyRecorder>>,
+    /// Durations of failed requests, up to the end of their last attempt,
+    /// kept apart so timeouts don't skew the success percentiles
+    pub failure_times: Arc<Mutex<LatencyRecorder>>,
+    /// Responses whose body was cut off at the read limit
+    pub truncated_responses: Arc<Mutex<u32>>,
+    /// Requests still in flight when the shutdown drain timed out
+    pub aborted_requests: Arc<Mutex<u32>>,
+    /// Failed requests by the kind of their last error
+    pub error_counts: Arc<Mutex<BTreeMap<ErrorKind, u32>>>,
+    /// Retried requests by the kind of error their first attempt hit
+    pub retry_outcomes: Arc<Mutex<BTreeMap<ErrorKind, RetryOutcome>>>,
+    /// Successful requests by the number of retries they needed
+    pub success_retries: Arc<Mutex<BTreeMap<u32, u32>>>,
+    /// Responses failed by --expect-header, keyed by the header that didn't match
+    pub header_mismatches: Arc<Mutex<BTreeMap<String, u32>>>,
+    /// The first few distinct --response-schema violations, as examples
+    pub schema_violations: Arc<Mutex<Vec<String>>>,
+    /// Example body sizes outside the --min-body-bytes/--max-body-bytes-expected
+    /// range, in the order first seen
+    pub unexpected_sizes: Arc<Mutex<Vec<usize>>>,
+    /// Successful responses counted by status and a hash of their body, kept
+    /// only for --idempotency-check
+    pub body_hashes: Option<Arc<Mutex<BodyHashes>>>,
+    /// Satisfied, tolerating and frustrated requests, kept only for
+    /// --apdex-threshold
+    pub apdex: Option<Arc<Mutex<Apdex>>>,
+    /// Requests over the deadline, kept only for --hard-deadline
+    pub deadline: Option<Arc<Mutex<DeadlineCheck>>>,
+    /// 429 and 503 responses steering concurrency, kept only for
+    /// --backpressure-aware
+    pub backpressure: Option<Arc<Mutex<Backpressure>>>,
+    /// Request counts by URL, outcome and latency bucket, kept only for
+    /// --folded
+    pub folded: Option<Arc<Mutex<FoldedLatencies>>>,
+    /// Requests in flight sampled through the run, kept for live runs but not
+    /// for --replay
+    pub in_flight: Option<Arc<Mutex<InFlightSamples>>>,
+    /// Client and server-reported times, kept only for --parse-server-timing
+    pub server_timings: Option<Arc<Mutex<ServerTimings>>>,
+    /// Yields between spawn batches, kept only when the concurrency is larger
+    /// than --spawn-batch
+    pub spawn_stalls: Option<Arc<Mutex<SpawnStalls>>>,
+    /// Dispatch punctuality against the schedule, kept only for --gap runs
+    pub rate_accuracy: Option<Arc<Mutex<RateAccuracy>>>,
+    /// Completed requests per target, kept only for --min-per-url
+    pub url_samples: Option<Arc<Mutex<UrlSamples>>>,
+    /// Requests and latencies per target, kept only for a weighted --url mix
+    pub url_stats: Option<Arc<Mutex<Vec<UrlStats>>>>,
+    /// Per-step and end-to-end latencies, kept only for request chains
+    pub journeys: Option<Arc<Mutex<JourneyTimes>>>,
+    /// Paces body reads across all workers for --max-bandwidth
+    pub bandwidth: Option<Arc<ByteRateLimiter>>,
+    /// Lookup times of the clients' resolver, kept only for --dns-timing
+    pub dns: Option<Arc<DnsTimings>>,
+    /// Every finished request as a CSV row, kept only for --csv
+    pub records: Option<Arc<RecordLog>>,
+    /// Response body bytes read so far, across all requests
+    pub bytes_received: Arc<AtomicU64>,
+    /// Response body bytes of every response: as read, or the
+    /// `Content-Length` of bodies left unread, for the throughput
+    pub total_bytes: Arc<AtomicU64>,
+    /// Request body bytes before and after gzip, counted only for
+    /// --compress-request
+    pub request_body_bytes: Arc<AtomicU64>,
+    pub compressed_body_bytes: Arc<AtomicU64>,
+    /// Stop the run once more body bytes than this were read (--max-bytes)
+    pub byte_cap: Option<u64>,
+    /// Latencies of received responses keyed by status class (2 for 2xx)
+    pub status_latencies: Arc<Mutex<BTreeMap<u16, LatencyRecorder>>>,
+    /// Received responses by status code, including those of retried attempts
+    pub status_counts: Arc<Mutex<BTreeMap<u16, u32>>>,
+    /// Every completed request on the run's timeline, kept only for time series
+    pub completions: Option<Arc<Mutex<Vec<Completion>>>>,
+    /// Wall-clock time the run started, for correlating results with logs.
+    /// Latencies and completion offsets use the monotonic clock instead, so
+    /// clock adjustments during a run can't distort them.
+    pub started_at: SystemTime,
+    started: Instant,
 }
 
 impl RequestMetrics {
-    pub fn new() -> Self {
+    /// Create metrics that record latencies into a histogram. With
+    /// `raw_latencies`, the raw samples are kept too, capped at `max_samples`.
+    /// With `timeseries`, every completion is kept with its finish time.
+    pub fn new(raw_latencies: bool, max_samples: Option<usize>, timeseries: bool) -> Self {
+        let raw = raw_latencies.then(|| LatencySamples::new(max_samples));
         Self {
             successful_requests: Arc::new(tokio::sync::Mutex::new(0)),
             failed_requests: Arc::new(tokio::sync::Mutex::new(0)),
-            request_times: Arc::new(Mutex::new(Vec::new())),
+            request_times: Arc::new(Mutex::new(LatencyRecorder::new(raw))),
+            failure_times: Arc::new(Mutex::new(LatencyRecorder::new(None))),
+            truncated_responses: Arc::new(Mutex::new(0)),
+            aborted_requests: Arc::new(Mutex::new(0)),
+            error_counts: Arc::new(Mutex::new(BTreeMap::new())),
+            retry_outcomes: Arc::new(Mutex::new(BTreeMap::new())),
+            success_retries: Arc::new(Mutex::new(BTreeMap::new())),
+            header_mismatches: Arc::new(Mutex::new(BTreeMap::new())),
+            schema_violations: Arc::new(Mutex::new(Vec::new())),
+            unexpected_sizes: Arc::new(Mutex::new(Vec::new())),
+            body_hashes: None,
+            apdex: None,
+            deadline: None,
+            backpressure: None,
+            folded: None,
+            in_flight: None,
+            server_timings: None,
+            spawn_stalls: None,
+            rate_accuracy: None,
+            url_samples: None,
+            url_stats: None,
+            journeys: None,
+            bandwidth: None,
+            dns: None,
+            records: None,
+            bytes_received: Arc::new(AtomicU64::new(0)),
+            total_bytes: Arc::new(AtomicU64::new(0)),
+            request_body_bytes: Arc::new(AtomicU64::new(0)),
+            compressed_body_bytes: Arc::new(AtomicU64::new(0)),
+            byte_cap: None,
+            status_latencies: Arc::new(Mutex::new(BTreeMap::new())),
+            status_counts: Arc::new(Mutex::new(BTreeMap::new())),
+            completions: timeseries.then(|| Arc::new(Mutex::new(Vec::new()))),
+            started_at: SystemTime::now(),
+            started: Instant::now(),
+        }
+    }
+
+    /// Add the counts and latencies of another run, for the overall report of
+    /// a --phases run. Optional trackers and example samples aren't merged.
+    pub async fn absorb(&self, other: &RequestMetrics) {
+        *self.successful_requests.lock().await += *other.successful_requests.lock().await;
+        *self.failed_requests.lock().await += *other.failed_requests.lock().await;
+        *self.truncated_responses.lock().await += *other.truncated_responses.lock().await;
+        *self.aborted_requests.lock().await += *other.aborted_requests.lock().await;
+        self.request_times
+            .lock()
+            .await
+            .merge(&*other.request_times.lock().await);
+        self.failure_times
+            .lock()
+            .await
+            .merge(&*other.failure_times.lock().await);
+        add_counts(
+            &mut *self.error_counts.lock().await,
+            &*other.error_counts.lock().await,
+        );
+        add_counts(
+            &mut *self.success_retries.lock().await,
+            &*other.success_retries.lock().await,
+        );
+        add_counts(
+            &mut *self.header_mismatches.lock().await,
+            &*other.header_mismatches.lock().await,
+        );
+        add_counts(
+            &mut *self.status_counts.lock().await,
+            &*other.status_counts.lock().await,
+        );
+        {
+            let mut outcomes = self.retry_outcomes.lock().await;
+            for (kind, outcome) in other.retry_outcomes.lock().await.iter() {
+                let merged = outcomes.entry(*kind).or_default();
+                merged.retried += outcome.retried;
+                merged.recovered += outcome.recovered;
+            }
+        }
+        {
+            let mut by_status = self.status_latencies.lock().await;
+            for (class, recorder) in other.status_latencies.lock().await.iter() {
+                by_status
+                    .entry(*class)
+                    .or_insert_with(|| LatencyRecorder::new(None))
+                    .merge(recorder);
+            }
+        }
+        self.bytes_received.fetch_add(
+            other.bytes_received.load(Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.total_bytes
+            .fetch_add(other.total_bytes.load(Ordering::Relaxed), Ordering::Relaxed);
+    }
+
+    /// Count a received response under its status code and status class.
+    async fn record_status(&self, status: StatusCode, latency: Duration) {
+        self.status_latencies
+            .lock()
+            .await
+            .entry(status.as_u16() / 100)
+            .or_insert_with(|| LatencyRecorder::new(None))
+            .record(latency);
+        *self
+            .status_counts
+            .lock()
+            .await
+            .entry(status.as_u16())
+            .or_insert(0) += 1;
+        if let Some(backpressure) = &self.backpressure {
+            backpressure.lock().await.record(status.as_u16());
+        }
+    }
+
+    /// Count a retried request under the kind of its first failure.
+    async fn record_retry_outcome(&self, first_failure: ErrorKind, recovered: bool) {
+        let mut outcomes = self.retry_outcomes.lock().await;
+        let outcome = outcomes.entry(first_failure).or_default();
+        outcome.retried += 1;
+        if recovered {
+            outcome.recovered += 1;
+        }
+    }
+
+    /// Start counting successful responses by status and body hash.
+    pub fn track_bodies(&mut self) {
+        self.body_hashes = Some(Arc::new(Mutex::new(BTreeMap::new())));
+    }
+
+    /// Start counting requests towards an Apdex score with target time `threshold`.
+    pub fn track_apdex(&mut self, threshold: Duration) {
+        self.apdex = Some(Arc::new(Mutex::new(Apdex::new(threshold))));
+    }
+
+    /// Count a finished request towards the Apdex score, if one is tracked.
+    pub async fn record_apdex(&self, latency: Duration, success: bool) {
+        if let Some(apdex) = &self.apdex {
+            let mut apdex = apdex.lock().await;
+            if success {
+                apdex.record(latency);
+            } else {
+                apdex.record_failure();
+            }
+        }
+    }
+
+    /// Start flagging requests that take longer than `deadline`.
+    pub fn track_deadline(&mut self, deadline: Duration) {
+        self.deadline = Some(Arc::new(Mutex::new(DeadlineCheck::new(deadline))));
+    }
+
+    /// Check a finished request against the deadline, if one is set.
+    pub async fn record_deadline(&self, latency: Duration) {
+        if let Some(deadline) = &self.deadline {
+            deadline.lock().await.record(latency);
+        }
+    }
+
+    /// Report the lookups `timings` records from now on with this run.
+    pub fn track_dns(&mut self, timings: Arc<DnsTimings>) {
+        timings.reset();
+        self.dns = Some(timings);
+    }
+
+    /// Write every request finished from now on to `log`.
+    pub fn track_records(&mut self, log: Arc<RecordLog>) {
+        self.records = Some(log);
+    }
+
+    /// Start adapting concurrency between `min` and `max` to backpressure.
+    pub fn track_backpressure(&mut self, min: u32, max: u32) {
+        self.backpressure = Some(Arc::new(Mutex::new(Backpressure::new(min, max))));
+    }
+
+    /// Start counting requests by URL, outcome and latency bucket.
+    pub fn track_folded(&mut self) {
+        self.folded = Some(Arc::new(Mutex::new(FoldedLatencies::default())));
+    }
+
+    /// Count a finished request by its outcome, the status code or the kind
+    /// of error, if folded stacks are tracked.
+    async fn record_folded(&self, params: &RequestParams, outcome: &str, latency: Duration) {
+        if let Some(folded) = &self.folded {
+            let request = format!("{} {}", params.method, redact_url(&params.url));
+            folded.lock().await.record(&request, outcome, latency);
+        }
+    }
+
+    /// Start keeping samples of the requests in flight.
+    pub fn track_in_flight(&mut self) {
+        self.in_flight = Some(Arc::new(Mutex::new(InFlightSamples::default())));
+    }
+
+    /// Start comparing client latency with Server-Timing durations.
+    pub fn track_server_timing(&mut self) {
+        self.server_timings = Some(Arc::new(Mutex::new(ServerTimings::default())));
+    }
+
+    /// Start timing the yields between batches of `batch_size` spawned requests.
+    pub fn track_spawn_stalls(&mut self, batch_size: u32) {
+        self.spawn_stalls = Some(Arc::new(Mutex::new(SpawnStalls::new(batch_size))));
+    }
+
+    /// Count a yield between spawn batches that took `waited`, if yields are
+    /// tracked.
+    pub async fn record_spawn_yield(&self, waited: Duration) {
+        if let Some(stalls) = &self.spawn_stalls {
+            stalls.lock().await.record(waited);
+        }
+    }
+
+    /// Start checking dispatches against a schedule of `requested` requests
+    /// per second, allowing each to start up to `tolerance` late.
+    pub fn track_rate(&mut self, requested: f64, tolerance: Duration) {
+        self.rate_accuracy = Some(Arc::new(Mutex::new(RateAccuracy::new(
+            requested, tolerance,
+        ))));
+    }
+
+    /// Count a dispatch that started `lateness` after its scheduled time, if
+    /// the schedule is tracked.
+    pub async fn record_dispatch(&self, lateness: Duration) {
+        if let Some(accuracy) = &self.rate_accuracy {
+            accuracy.lock().await.record(lateness);
+        }
+    }
+
+    /// Start counting completed requests per target, to be checked against
+    /// `minimum`.
+    pub fn track_url_samples(&mut self, targets: &[Target], minimum: u32) {
+        let urls = targets.iter().map(|target| target.url.clone());
+        self.url_samples = Some(Arc::new(Mutex::new(UrlSamples::new(urls, minimum))));
+    }
+
+    /// Start counting requests and latencies per target, each drawn with
+    /// its share of `weights`.
+    pub fn track_url_stats(&mut self, targets: &[Target], weights: &[f64]) {
+        let stats = targets
+            .iter()
+            .zip(weights)
+            .map(|(target, &weight)| UrlStats::new(target.url.clone(), weight))
+            .collect();
+        self.url_stats = Some(Arc::new(Mutex::new(stats)));
+    }
+
+    /// Whether some target still has fewer completed requests than
+    /// --min-per-url asks for.
+    pub async fn urls_short(&self) -> bool {
+        match &self.url_samples {
+            Some(samples) => !samples.lock().await.short().is_empty(),
+            None => false,
+        }
+    }
+
+    /// Start timing every step of a request chain, and the whole chain.
+    pub fn track_journeys(&mut self, steps: &[Target]) {
+        self.journeys = Some(Arc::new(Mutex::new(JourneyTimes::new(steps))));
+    }
+
+    /// Read response bodies no faster than `limiter` allows.
+    pub fn limit_bandwidth(&mut self, limiter: Arc<ByteRateLimiter>) {
+        self.bandwidth = Some(limiter);
+    }
+
+    /// Stop the run once more than `cap` body bytes were read in total.
+    pub fn cap_bytes(&mut self, cap: u64) {
+        self.byte_cap = Some(cap);
+    }
+
+    /// Whether the run has read more body bytes than --max-bytes allows.
+    pub fn byte_cap_exceeded(&self) -> bool {
+        self.byte_cap
+            .is_some_and(|cap| self.bytes_received.load(Ordering::Relaxed) > cap)
+    }
+
+    /// Count a response body under its status and hash, if bodies are tracked.
+    async fn record_body(&self, status: u16, body: &[u8]) {
+        if let Some(body_hashes) = &self.body_hashes {
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            body.hash(&mut hasher);
+            *body_hashes
+                .lock()
+                .await
+                .entry((status, hasher.finish()))
+                .or_insert(0) += 1;
+        }
+    }
+
+    async fn record_completion(&self, latency: Duration, success: bool) {
+        if let Some(completions) = &self.completions {
+            completions.lock().await.push(Completion {
+                at: self.started.elapsed(),
+                latency,
+                success,
+            });
         }
     }
 }
 
+/// Add every count of `from` to `into`.
+fn add_counts<K: Ord + Clone>(into: &mut BTreeMap<K, u32>, from: &BTreeMap<K, u32>) {
+    for (key, count) in from {
+        *into.entry(key.clone()).or_insert(0) += count;
+    }
+}
+
+/// Why a request failed, for the error breakdown in the summary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ErrorKind {
+    Timeout,
+    /// No connection within --connect-timeout, as opposed to a slow response
+    ConnectTimeout,
+    Connect,
+    /// The TLS handshake or session failed, e.g. an untrusted or expired
+    /// certificate or no protocol version both sides support
+    Tls,
+    /// The server closed the HTTP/2 connection
+    #[cfg(feature = "http2")]
+    Http2GoAway,
+    /// The server reset the HTTP/2 stream
+    #[cfg(feature = "http2")]
+    Http2Reset,
+    Body,
+    /// The connection broke while the response body was being read, after
+    /// the status and headers had arrived
+    IncompleteResponse,
+    /// The response lacked a header required by --expect-header
+    HeaderMismatch,
+    /// The response body didn't match --response-schema
+    SchemaViolation,
+    /// The response body was smaller or larger than the expected range
+    UnexpectedSize,
+    /// The server still answered 401 after the NTLM handshake
+    #[cfg(feature = "ntlm")]
+    Auth,
+    /// A 4xx response under a --fail-on policy that fails client errors
+    ClientStatus,
+    /// A 5xx response under a --fail-on policy that fails server errors
+    ServerStatus,
+    Other,
+}
+
+impl std::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ErrorKind::Timeout => write!(f, "timeout"),
+            ErrorKind::ConnectTimeout => write!(f, "connect timeout"),
+            ErrorKind::Connect => write!(f, "connect"),
+            ErrorKind::Tls => write!(f, "TLS"),
+            #[cfg(feature = "http2")]
+            ErrorKind::Http2GoAway => write!(f, "HTTP/2 GOAWAY"),
+            #[cfg(feature = "http2")]
+            ErrorKind::Http2Reset => write!(f, "HTTP/2 RST_STREAM"),
+            ErrorKind::Body => write!(f, "body"),
+            ErrorKind::IncompleteResponse => write!(f, "incomplete response"),
+            ErrorKind::HeaderMismatch => write!(f, "header mismatch"),
+            ErrorKind::SchemaViolation => write!(f, "schema violation"),
+            ErrorKind::UnexpectedSize => write!(f, "unexpected size"),
+            #[cfg(feature = "ntlm")]
+            ErrorKind::Auth => write!(f, "NTLM auth"),
+            ErrorKind::ClientStatus => write!(f, "4xx status"),
+            ErrorKind::ServerStatus => write!(f, "5xx status"),
+            ErrorKind::Other => write!(f, "other"),
+        }
+    }
+}
+
+pub fn classify_error(err: &reqwest::Error) -> ErrorKind {
+    #[cfg(feature = "http2")]
+    if let Some(kind) = classify_http2_error(err) {
+        return kind;
+    }
+
+    // A failed handshake surfaces as a connect error, so TLS is checked first
+    if is_tls_error(err) {
+        ErrorKind::Tls
+    } else if err.is_timeout() && err.is_connect() {
+        ErrorKind::ConnectTimeout
+    } else if err.is_timeout() {
+        ErrorKind::Timeout
+    } else if err.is_connect() {
+        ErrorKind::Connect
+    } else if err.is_body() || err.is_decode() {
+        ErrorKind::Body
+    } else {
+        ErrorKind::Other
+    }
+}
+
+/// Which response statuses fail a request, and are retried like transport
+/// errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FailOn {
+    /// Every response counts as successful
+    None,
+    /// 4xx responses fail
+    ClientErrors,
+    /// 5xx responses fail
+    #[default]
+    ServerErrors,
+    /// 4xx and 5xx responses fail
+    AllErrors,
+}
+
+impl FailOn {
+    /// The kind of failure `status` is under this policy, if it is one.
+    pub fn failure(self, status: StatusCode) -> Option<ErrorKind> {
+        let client = matches!(self, FailOn::ClientErrors | FailOn::AllErrors);
+        let server = matches!(self, FailOn::ServerErrors | FailOn::AllErrors);
+        if client && status.is_client_error() {
+            Some(ErrorKind::ClientStatus)
+        } else if server && status.is_server_error() {
+            Some(ErrorKind::ServerStatus)
+        } else {
+            None
+        }
+    }
+}
+
+impl std::fmt::Display for FailOn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FailOn::None => write!(f, "none"),
+            FailOn::ClientErrors => write!(f, "client-errors"),
+            FailOn::ServerErrors => write!(f, "server-errors"),
+            FailOn::AllErrors => write!(f, "all-errors"),
+        }
+    }
+}
+
+/// Classify an error hit while reading a response body. Anything but a
+/// timeout or an HTTP/2 stream error means the server cut the response short.
+pub fn classify_read_error(err: &reqwest::Error) -> ErrorKind {
+    match classify_error(err) {
+        ErrorKind::Timeout => ErrorKind::Timeout,
+        #[cfg(feature = "http2")]
+        kind @ (ErrorKind::Http2GoAway | ErrorKind::Http2Reset) => kind,
+        _ => ErrorKind::IncompleteResponse,
+    }
+}
+
+/// Look for a rustls error anywhere in the error's source chain. rustls
+/// errors reach hyper wrapped in (nested) `io::Error`s, whose `source()`
+/// skips the wrapped error, so those are stepped into instead.
+fn is_tls_error(err: &(dyn std::error::Error + 'static)) -> bool {
+    let mut source = err.source();
+    while let Some(err) = source {
+        if err.is::<rustls::Error>() {
+            return true;
+        }
+        source = match err
+            .downcast_ref::<std::io::Error>()
+            .and_then(|io_err| io_err.get_ref())
+        {
+            Some(wrapped) => Some(wrapped),
+            None => err.source(),
+        };
+    }
+    false
+}
+
+/// Look for an h2 protocol error anywhere in the error's source chain.
+#[cfg(feature = "http2")]
+fn classify_http2_error(err: &(dyn std::error::Error + 'static)) -> Option<ErrorKind> {
+    let mut source = err.source();
+    while let Some(err) = source {
+        if let Some(h2_err) = err.downcast_ref::<h2::Error>() {
+            if h2_err.is_go_away() {
+                return Some(ErrorKind::Http2GoAway);
+            }
+            if h2_err.is_reset() {
+                return Some(ErrorKind::Http2Reset);
+            }
+        }
+        source = err.source();
+    }
+    None
+}
+
+/// What happened to requests whose first attempt failed and were retried.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RetryOutcome {
+    pub retried: u32,
+    /// Retried requests that eventually succeeded
+    pub recovered: u32,
+}
+
+impl RetryOutcome {
+    /// Percentage of retried requests that eventually succeeded.
+    pub fn recovery_rate(&self) -> f64 {
+        if self.retried == 0 {
+            return 0.0;
+        }
+        self.recovered as f64 * 100.0 / self.retried as f64
+    }
+}
+
+/// A response header that every response must carry with a given value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeaderExpectation {
+    pub name: String,
+    pub value: String,
+}
+
+impl HeaderExpectation {
+    /// Parse `Name: value`. Surrounding whitespace is ignored.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (name, value) = spec
+            .split_once(':')
+            .ok_or_else(|| format!("Invalid expected header '{}': expected Name: value", spec))?;
+        let name = name.trim();
+        if reqwest::header::HeaderName::from_bytes(name.as_bytes()).is_err() {
+            return Err(format!("Invalid expected header name '{}'", name));
+        }
+        Ok(Self {
+            name: name.to_string(),
+            value: value.trim().to_string(),
+        })
+    }
+
+    /// Whether any value of the header equals the expected one. Names match
+    /// case-insensitively, values exactly.
+    pub fn matches(&self, headers: &HeaderMap) -> bool {
+        headers
+            .get_all(self.name.as_str())
+            .iter()
+            .any(|value| value.to_str().is_ok_and(|value| value.trim() == self.value))
+    }
+}
+
+/// The range of body sizes, in bytes, a response must fall in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BodySizeBounds {
+    pub min: Option<usize>,
+    pub max: Option<usize>,
+}
+
+impl BodySizeBounds {
+    pub fn contains(&self, size: usize) -> bool {
+        self.min.is_none_or(|min| size >= min) && self.max.is_none_or(|max| size <= max)
+    }
+}
+
+/// Whether a request body should be attached for `method`. Only verbs that
+/// semantically carry a payload get one; GET, HEAD and friends never do.
+pub fn method_takes_body(method: &Method) -> bool {
+    matches!(*method, Method::POST | Method::PUT | Method::PATCH)
+}
+
+/// Everything needed to send one request.
+#[derive(Debug, Clone)]
+pub struct RequestParams {
+    pub method: Method,
+    pub url: String,
+    pub body: Option<String>,
+    pub headers: Vec<(String, String)>,
+    pub timeout: Duration,
+    /// Each retry's timeout is the previous attempt's times this factor
+    pub timeout_growth: f64,
+    /// Backoff before the first retry, doubled for each later one
+    pub retry_base_delay: Duration,
+    /// Longest backoff between retries
+    pub retry_max_delay: Duration,
+    /// Read the response body, keeping at most this many bytes. `None` leaves
+    /// the body unread.
+    pub read_body: Option<usize>,
+    /// Supplies the `Authorization` header, overriding any configured one
+    pub auth: Option<Arc<AuthRefresher>>,
+    /// Correlation header name and the unique ID it carries; retries reuse the ID
+    pub correlation: Option<(String, String)>,
+    /// Headers a response must carry to count as successful
+    pub expect_headers: Arc<Vec<HeaderExpectation>>,
+    /// Schema that 2xx response bodies must match
+    pub response_schema: Option<Arc<ResponseSchema>>,
+    /// Sizes every response body must fall between
+    pub body_size: Option<BodySizeBounds>,
+    /// Split each success line's duration into time to the response headers
+    /// and time reading the body
+    pub timing_breakdown: bool,
+    /// Gzip the body and send it with `Content-Encoding: gzip`
+    pub compress_body: bool,
+    /// Response statuses that fail the attempt
+    pub fail_on: FailOn,
+    /// Answer NTLM and Negotiate challenges with these credentials
+    #[cfg(feature = "ntlm")]
+    pub ntlm: Option<Arc<NtlmCredentials>>,
+}
+
+/// A received response, with its body if one was read.
+pub struct CompletedResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Option<Vec<u8>>,
+    /// Whether the body was cut off at the read limit
+    pub truncated: bool,
+    /// Body bytes that arrived, those drained past the read limit included,
+    /// or the `Content-Length` of a body left unread (0 if it had none)
+    pub body_bytes: u64,
+    /// How long the attempt that got this response took, as recorded in the
+    /// aggregate latencies; earlier failed attempts and backoff are left out
+    pub latency: Duration,
+}
+
+/// The body size as read, or the `Content-Length` of a body left unread.
+fn response_size(response: &CompletedResponse) -> Option<u64> {
+    match &response.body {
+        Some(body) => Some(body.len() as u64),
+        None => response
+            .headers
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok()?.parse().ok()),
+    }
+}
+
+/// Read up to `limit` bytes of the body. Reading stops at the limit rather
+/// than draining the rest, so a runaway response can't exhaust memory. Each
+/// chunk waits for `bandwidth` before the next one is read.
+async fn read_response(
+    mut response: reqwest::Response,
+    limit: Option<usize>,
+    bandwidth: Option<&ByteRateLimiter>,
+    received: &AtomicU64,
+) -> Result<CompletedResponse, reqwest::Error> {
+    let status = response.status();
+    let headers = response.headers().clone();
+
+    let Some(limit) = limit else {
+        return Ok(CompletedResponse {
+            status,
+            headers,
+            body: None,
+            truncated: false,
+            body_bytes: response.content_length().unwrap_or(0),
+            latency: Duration::ZERO,
+        });
+    };
+
+    // Past the limit the body is drained without being kept, so a huge
+    // response can't exhaust memory but still counts toward throughput
+    let mut body = Vec::new();
+    let mut body_bytes = 0;
+    while let Some(chunk) = response.chunk().await? {
+        received.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+        body_bytes += chunk.len() as u64;
+        if let Some(bandwidth) = bandwidth {
+            bandwidth.consume(chunk.len()).await;
+        }
+        let room = limit - body.len();
+        body.extend_from_slice(&chunk[..chunk.len().min(room)]);
+    }
+
+    Ok(CompletedResponse {
+        status,
+        headers,
+        truncated: body_bytes > body.len() as u64,
+        body: Some(body),
+        body_bytes,
+        latency: Duration::ZERO,
+    })
+}
+
+/// Timeout of attempt `attempt` (0 for the first): `base * growth^attempt`.
+pub fn attempt_timeout(base: Duration, growth: f64, attempt: u32) -> Duration {
+    Duration::try_from_secs_f64(base.as_secs_f64() * growth.powi(attempt as i32))
+        .unwrap_or(Duration::MAX)
+}
+
+/// Delay before the first retry when --retry-base-delay-ms isn't given.
+pub const DEFAULT_RETRY_BASE_DELAY: Duration = Duration::from_millis(200);
+
+/// Longest delay between retries when --retry-max-delay-ms isn't given.
+pub const DEFAULT_RETRY_MAX_DELAY: Duration = Duration::from_secs(5);
+
+/// Delay after the failure of attempt `attempt` (0 for the first):
+/// `base * 2^attempt`, capped at `max`, of which a random half is jitter
+/// drawn from `rng`, so workers that failed together don't retry together.
+pub fn backoff_delay(attempt: u32, base: Duration, max: Duration, rng: &mut impl Rng) -> Duration {
+    let ceiling = base
+        .checked_mul(2u32.saturating_pow(attempt))
+        .unwrap_or(Duration::MAX)
+        .min(max);
+    let half = ceiling / 2;
+    half + half.mul_f64(rng.random::<f64>())
+}
+
+/// ` [X-Request-ID: ...]` for log lines of requests that carry a correlation ID.
+fn correlation_suffix(params: &RequestParams) -> String {
+    match &params.correlation {
+        Some((name, id)) => format!(" [{}: {}]", name, id),
+        None => String::new(),
+    }
+}
+
+/// `, ttfb: ..., body: ...` for success lines with a timing breakdown. The
+/// time to first byte covers DNS, connect and TLS too when the request had to
+/// open a connection; the client doesn't expose those phases separately.
+fn timing_suffix(ttfb: Duration, total: Duration) -> String {
+    format!(", ttfb: {:?}, body: {:?}", ttfb, total.saturating_sub(ttfb))
+}
+
+/// The request for one attempt. `authorization` replaces any configured
+/// `Authorization` header.
+fn build_request(
+    client: &reqwest::Client,
+    params: &RequestParams,
+    body: Option<&[u8]>,
+    attempt: u32,
+    authorization: Option<&str>,
+) -> reqwest::RequestBuilder {
+    let mut request = client
+        .request(params.method.clone(), &params.url)
+        .timeout(attempt_timeout(
+            params.timeout,
+            params.timeout_growth,
+            attempt,
+        ));
+    for (name, value) in &params.headers {
+        if authorization.is_some() && name.eq_ignore_ascii_case("authorization") {
+            continue;
+        }
+        request = request.header(name, value);
+    }
+    if let Some(authorization) = authorization {
+        request = request.header(reqwest::header::AUTHORIZATION, authorization);
+    }
+    if let Some((name, id)) = &params.correlation {
+        request = request.header(name, id);
+    }
+    if let Some(body) = body {
+        if params.compress_body {
+            request = request.header(reqwest::header::CONTENT_ENCODING, "gzip");
+        }
+        request = request.body(body.to_vec());
+    }
+    request
+}
+
+/// The body to send for `params`, gzipped for --compress-request. It is
+/// prepared once per request, so retries send the same bytes.
+fn request_body(params: &RequestParams, metrics: &RequestMetrics) -> Option<Vec<u8>> {
+    let body = params.body.as_ref()?;
+    if !params.compress_body {
+        return Some(body.clone().into_bytes());
+    }
+    let compressed = gzip(body.as_bytes());
+    metrics
+        .request_body_bytes
+        .fetch_add(body.len() as u64, Ordering::Relaxed);
+    metrics
+        .compressed_body_bytes
+        .fetch_add(compressed.len() as u64, Ordering::Relaxed);
+    Some(compressed)
+}
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    // Writing to a Vec can't fail
+    encoder.write_all(data).expect("gzip into memory");
+    encoder.finish().expect("gzip into memory")
+}
+
+/// Send the attempt, running the NTLM handshake if the server challenges it.
+/// A connection that already authenticated gets its response straight away.
+async fn send_request(
+    client: &reqwest::Client,
+    params: &RequestParams,
+    body: Option<&[u8]>,
+    attempt: u32,
+    authorization: Option<&str>,
+) -> Result<reqwest::Response, (ErrorKind, String)> {
+    let response = build_request(client, params, body, attempt, authorization)
+        .send()
+        .await
+        .map_err(|err| (classify_error(&err), err.to_string()))?;
+
+    #[cfg(feature = "ntlm")]
+    if let (Some(credentials), StatusCode::UNAUTHORIZED) = (&params.ntlm, response.status()) {
+        if let Some(scheme) = ntlm::offered_scheme(response.headers()) {
+            return ntlm_handshake(client, params, body, attempt, credentials, scheme, response)
+                .await;
+        }
+    }
+    Ok(response)
+}
+
+/// Answer a 401 that offers `scheme` with a negotiate message, then answer
+/// its challenge. Each 401 body is read to the end first, so the next message
+/// goes out on the same connection: NTLM authenticates the connection, not
+/// the request.
+#[cfg(feature = "ntlm")]
+async fn ntlm_handshake(
+    client: &reqwest::Client,
+    params: &RequestParams,
+    body: Option<&[u8]>,
+    attempt: u32,
+    credentials: &NtlmCredentials,
+    scheme: &str,
+    rejected: reqwest::Response,
+) -> Result<reqwest::Response, (ErrorKind, String)> {
+    let send = |authorization: String| async move {
+        build_request(client, params, body, attempt, Some(&authorization))
+            .send()
+            .await
+            .map_err(|err| (classify_error(&err), err.to_string()))
+    };
+    let drain = |response: reqwest::Response| async move {
+        response
+            .bytes()
+            .await
+            .map_err(|err| (classify_read_error(&err), err.to_string()))
+    };
+
+    drain(rejected).await?;
+    let challenged = send(format!("{} {}", scheme, ntlm::negotiate_message())).await?;
+    let challenge = ntlm::challenge_token(challenged.headers(), scheme)
+        .filter(|_| challenged.status() == StatusCode::UNAUTHORIZED)
+        .map(Challenge::parse);
+    let challenge = match challenge {
+        Some(Ok(challenge)) => challenge,
+        Some(Err(e)) => return Err((ErrorKind::Auth, format!("NTLM {}", e))),
+        None => {
+            return Err((
+                ErrorKind::Auth,
+                format!(
+                    "NTLM negotiate got {} without a challenge",
+                    challenged.status().as_u16()
+                ),
+            ))
+        }
+    };
+    drain(challenged).await?;
+
+    send(format!(
+        "{} {}",
+        scheme,
+        ntlm::authenticate_message(credentials, &challenge)
+    ))
+    .await
+}
+
+/// The failure a response with `status` is for this request, if any.
+fn status_failure(params: &RequestParams, status: StatusCode) -> Option<(ErrorKind, String)> {
+    // Rejected credentials stay rejected, and retrying them only risks
+    // locking the account
+    #[cfg(feature = "ntlm")]
+    if params.ntlm.is_some() && status == StatusCode::UNAUTHORIZED {
+        return Some((
+            ErrorKind::Auth,
+            "NTLM authentication was rejected (401)".to_string(),
+        ));
+    }
+    let kind = params.fail_on.failure(status)?;
+    Some((kind, format!("HTTP status {}", status)))
+}
+
 pub async fn make_request_with_retry(
     client: &reqwest::Client,
-    url: &str,
-    timeout: Duration,
+    params: &RequestParams,
     retries: u32,
     metrics: &RequestMetrics,
-) -> Result<(), Box<dyn std::error::Error>> {
+) -> Result<CompletedResponse, Box<dyn std::error::Error + Send + Sync>> {
     let mut last_err: Option<String> = None;
+    let mut last_kind = ErrorKind::Other;
+    let mut first_failure: Option<ErrorKind> = None;
     let mut elapsed = Duration::from_secs(0);
+    let body = request_body(params, metrics);
+
+    // The --csv row: the status and size of the last response, if any
+    let started_at = SystemTime::now();
+    let mut attempts = 0;
+    let mut last_response: Option<(u16, Option<u64>)> = None;
+    let record_request = |attempts, response: Option<(u16, Option<u64>)>, duration, success| {
+        if let Some(log) = &metrics.records {
+            log.write(&RequestRecord {
+                timestamp_ms: started_at
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis() as u64,
+                attempts,
+                status: response.map(|(status, _)| status),
+                duration,
+                success,
+                request_bytes: Some(body.as_ref().map_or(0, |body| body.len() as u64)),
+                response_bytes: response.and_then(|(_, size)| size),
+            });
+        }
+    };
 
     for attempt in 0..=retries {
         let start = Instant::now();
+        attempts = attempt + 1;
 
-        let request_result = client.get(url).timeout(timeout).send().await;
+        let authorization = match &params.auth {
+            Some(auth) => auth.header_value().await,
+            None => None,
+        };
+
+        let mut headers_received = None;
+        let request_result = match send_request(
+            client,
+            params,
+            body.as_deref(),
+            attempt,
+            authorization.as_deref(),
+        )
+        .await
+        {
+            Ok(response) => {
+                headers_received = Some(start.elapsed());
+                read_response(
+                    response,
+                    params.read_body,
+                    metrics.bandwidth.as_deref(),
+                    &metrics.bytes_received,
+                )
+                .await
+                .map_err(|err| (classify_read_error(&err), err.to_string()))
+            }
+            Err(failure) => Err(failure),
+        };
 
         elapsed = start.elapsed();
 
+        // Every response is counted under its status, then judged by the
+        // --fail-on policy, so a failing status is retried like a transport
+        // error
+        let request_result = match request_result {
+            Ok(mut response) => {
+                response.latency = elapsed;
+                metrics.record_status(response.status, elapsed).await;
+                metrics
+                    .total_bytes
+                    .fetch_add(response.body_bytes, Ordering::Relaxed);
+                last_response = Some((response.status.as_u16(), response_size(&response)));
+                if let (Some(auth), StatusCode::UNAUTHORIZED) = (&params.auth, response.status) {
+                    auth.refresh_after_rejection(authorization.as_deref()).await;
+                }
+                status_failure(params, response.status).map_or(Ok(response), Err)
+            }
+            Err(failure) => {
+                last_response = None;
+                Err(failure)
+            }
+        };
+
         match request_result {
             Ok(response) => {
+                // A response without an expected header fails the request
+                // outright; sending it again wouldn't change the headers
+                if let Some(expected) = params
+                    .expect_headers
+                    .iter()
+                    .find(|expected| !expected.matches(&response.headers))
+                {
+                    {
+                        let mut mismatches = metrics.header_mismatches.lock().await;
+                        *mismatches.entry(expected.name.clone()).or_insert(0) += 1;
+                    }
+                    let actual = response
+                        .headers
+                        .get(expected.name.as_str())
+                        .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned());
+                    last_err = Some(match actual {
+                        Some(actual) => format!(
+                            "expected header {}: {}, got {}",
+                            expected.name, expected.value, actual
+                        ),
+                        None => format!(
+                            "expected header {}: {}, but it is missing",
+                            expected.name, expected.value
+                        ),
+                    });
+                    last_kind = ErrorKind::HeaderMismatch;
+                    break;
+                }
+
+                // Partial content and error stubs come back with a success
+                // status just the same, so the size is checked on its own
+                if let (Some(bounds), Some(body)) = (&params.body_size, &response.body) {
+                    if !bounds.contains(body.len()) {
+                        {
+                            let mut samples = metrics.unexpected_sizes.lock().await;
+                            if samples.len() < MAX_SIZE_SAMPLES && !samples.contains(&body.len()) {
+                                samples.push(body.len());
+                            }
+                        }
+                        last_err = Some(format!("unexpected body size: {} bytes", body.len()));
+                        last_kind = ErrorKind::UnexpectedSize;
+                        break;
+                    }
+                }
+
+                // A cut-off body can't be judged, so truncated responses are
+                // left to the truncation count
+                if let (Some(schema), Some(body), false) =
+                    (&params.response_schema, &response.body, response.truncated)
+                {
+                    if response.status.is_success() {
+                        if let Err(violation) = schema.validate(body) {
+                            {
+                                let mut samples = metrics.schema_violations.lock().await;
+                                if samples.len() < MAX_SCHEMA_SAMPLES
+                                    && !samples.contains(&violation)
+                                {
+                                    samples.push(violation.clone());
+                                }
+                            }
+                            last_err = Some(format!("schema violation: {}", violation));
+                            last_kind = ErrorKind::SchemaViolation;
+                            break;
+                        }
+                    }
+                }
+
                 // Record successful request
                 {
                     let mut times = metrics.request_times.lock().await;
-                    times.push(elapsed);
+                    times.record(elapsed);
                 }
 
-                println!(
-                    "{}Request successful (Duration: {:?}) {}{}",
+                let timing = match headers_received.filter(|_| params.timing_breakdown) {
+                    Some(ttfb) => timing_suffix(ttfb, elapsed),
+                    None => String::new(),
+                };
+                logln!(
+                    "{}Request successful (Duration: {:?}{}) {}{}{}",
                     "".green(),
                     elapsed,
-                    response.status().as_u16(),
+                    timing,
+                    response.status.as_u16(),
+                    correlation_suffix(params),
                     "".clear()
                 );
 
@@ -57,21 +1177,72 @@ pub async fn make_request_with_retry(
                     let mut successful = metrics.successful_requests.lock().await;
                     *successful += 1;
                 }
-                return Ok(());
+                *metrics
+                    .success_retries
+                    .lock()
+                    .await
+                    .entry(attempt)
+                    .or_insert(0) += 1;
+                metrics.record_completion(elapsed, true).await;
+                metrics.record_apdex(elapsed, true).await;
+                metrics.record_deadline(elapsed).await;
+                metrics
+                    .record_folded(params, response.status.as_str(), elapsed)
+                    .await;
+                if let Some(timings) = &metrics.server_timings {
+                    timings.lock().await.record(&response.headers, elapsed);
+                }
+                if response.truncated {
+                    let mut truncated = metrics.truncated_responses.lock().await;
+                    *truncated += 1;
+                }
+                if let Some(body) = &response.body {
+                    metrics.record_body(response.status.as_u16(), body).await;
+                }
+                if let Some(kind) = first_failure {
+                    metrics.record_retry_outcome(kind, true).await;
+                }
+                record_request(attempts, last_response, elapsed, true);
+                return Ok(response);
             }
-            Err(err) => {
-                last_err = Some(err.to_string());
+            Err((kind, err)) => {
+                last_err = Some(err);
+                last_kind = kind;
+                #[cfg(feature = "ntlm")]
+                if kind == ErrorKind::Auth {
+                    break;
+                }
+                if attempt == 0 && retries > 0 {
+                    first_failure = Some(last_kind);
+                }
 
                 if attempt < retries {
-                    println!(
-                        "{}Request failed (attempt {}/{}): {}. Retrying...{}",
+                    // A growing timeout is worth logging, since it differs per attempt
+                    let next_timeout = if params.timeout_growth == 1.0 {
+                        String::new()
+                    } else {
+                        format!(
+                            " (timeout {:?})",
+                            attempt_timeout(params.timeout, params.timeout_growth, attempt + 1)
+                        )
+                    };
+                    logln!(
+                        "{}Request failed (attempt {}/{}): {}. Retrying{}...{}{}",
                         "".yellow(),
                         attempt + 1,
                         retries + 1,
                         last_err.as_ref().unwrap_or(&"Unknown error".to_string()),
+                        next_timeout,
+                        correlation_suffix(params),
                         "".clear()
                     );
-                    sleep(Duration::from_millis(200)).await;
+                    let delay = backoff_delay(
+                        attempt,
+                        params.retry_base_delay,
+                        params.retry_max_delay,
+                        &mut rand::rng(),
+                    );
+                    sleep(delay).await;
                 }
             }
         }
@@ -79,15 +1250,16 @@ pub async fn make_request_with_retry(
 
     // Record failed request
     {
-        let mut times = metrics.request_times.lock().await;
-        times.push(elapsed);
+        let mut times = metrics.failure_times.lock().await;
+        times.record(elapsed);
     }
 
-    println!(
-        "{}Error making request: {} (Duration: {:?}){}",
+    logln!(
+        "{}Error making request: {} (Duration: {:?}){}{}",
         "".red(),
         last_err.as_ref().unwrap_or(&"Unknown error".to_string()),
         elapsed,
+        correlation_suffix(params),
         "".clear()
     );
 
@@ -95,6 +1267,20 @@ pub async fn make_request_with_retry(
         let mut failed = metrics.failed_requests.lock().await;
         *failed += 1;
     }
+    {
+        let mut error_counts = metrics.error_counts.lock().await;
+        *error_counts.entry(last_kind).or_insert(0) += 1;
+    }
+    if let Some(kind) = first_failure {
+        metrics.record_retry_outcome(kind, false).await;
+    }
+    metrics.record_completion(elapsed, false).await;
+    metrics.record_apdex(elapsed, false).await;
+    metrics.record_deadline(elapsed).await;
+    metrics
+        .record_folded(params, &last_kind.to_string(), elapsed)
+        .await;
+    record_request(attempts, last_response, elapsed, false);
 
     match last_err {
         Some(err) => Err(err.into()),
@@ -105,12 +1291,40 @@ pub async fn make_request_with_retry(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
     use std::time::Duration;
 
+    /// A plain GET of `url` with a 5s timeout and nothing else configured;
+    /// tests override the fields they exercise.
+    fn test_params(url: &str) -> RequestParams {
+        RequestParams {
+            method: Method::GET,
+            url: url.to_string(),
+            body: None,
+            headers: Vec::new(),
+            timeout: Duration::from_secs(5),
+            timeout_growth: 1.0,
+            retry_base_delay: DEFAULT_RETRY_BASE_DELAY,
+            retry_max_delay: DEFAULT_RETRY_MAX_DELAY,
+            read_body: None,
+            auth: None,
+            correlation: None,
+            expect_headers: Arc::new(Vec::new()),
+            response_schema: None,
+            body_size: None,
+            timing_breakdown: false,
+            compress_body: false,
+            fail_on: FailOn::default(),
+            #[cfg(feature = "ntlm")]
+            ntlm: None,
+        }
+    }
+
     // Simple test for RequestMetrics
     #[test]
     fn test_request_metrics_new() {
-        let metrics = RequestMetrics::new();
+        let metrics = RequestMetrics::new(false, None, false);
 
         // We can't use async/await in a regular #[test], so we'll just check the initial values
         assert!(Arc::strong_count(&metrics.successful_requests) == 1);
@@ -118,6 +1332,24 @@ mod tests {
         assert!(Arc::strong_count(&metrics.request_times) == 1);
     }
 
+    #[test]
+    fn test_timing_suffix() {
+        assert_eq!(
+            timing_suffix(Duration::from_millis(40), Duration::from_millis(55)),
+            ", ttfb: 40ms, body: 15ms"
+        );
+    }
+
+    #[test]
+    fn test_method_takes_body() {
+        assert!(method_takes_body(&Method::POST));
+        assert!(method_takes_body(&Method::PUT));
+        assert!(method_takes_body(&Method::PATCH));
+        assert!(!method_takes_body(&Method::GET));
+        assert!(!method_takes_body(&Method::HEAD));
+        assert!(!method_takes_body(&Method::DELETE));
+    }
+
     // For the HTTP request tests, we'll use a simpler approach without mockito
     // since we're having runtime issues
 
@@ -126,13 +1358,634 @@ mod tests {
     async fn test_make_request_with_retry_invalid_url() {
         let url = "https://invalid-url-that-does-not-exist-12345.com";
         let client = reqwest::Client::new();
-        let metrics = RequestMetrics::new();
+        let metrics = RequestMetrics::new(false, None, false);
         let timeout = Duration::from_secs(1);
         let retries = 0; // No retries to make the test faster
 
-        let result = make_request_with_retry(&client, url, timeout, retries, &metrics).await;
+        let params = RequestParams {
+            timeout,
+            ..test_params(url)
+        };
+
+        let result = make_request_with_retry(&client, &params, retries, &metrics).await;
+
+        assert!(result.is_err());
+        assert_eq!(*metrics.failed_requests.lock().await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_failed_request_is_classified() {
+        let client = reqwest::Client::new();
+        let metrics = RequestMetrics::new(false, None, false);
+        // Nothing listens on the discard port, so the connection is refused
+        let params = RequestParams {
+            timeout: Duration::from_secs(1),
+            ..test_params("http://127.0.0.1:9")
+        };
+
+        let result = make_request_with_retry(&client, &params, 0, &metrics).await;
+
+        assert!(result.is_err());
+        let error_counts = metrics.error_counts.lock().await;
+        assert_eq!(error_counts.get(&ErrorKind::Connect), Some(&1));
+        // Without retries there is nothing to judge retries by
+        assert!(metrics.retry_outcomes.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_slow_connect_is_classified_as_connect_timeout() {
+        // A listener that never accepts: once its backlog is full, the
+        // kernel drops further handshakes and a connect hangs
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut backlog = Vec::new();
+        while let Ok(stream) =
+            std::net::TcpStream::connect_timeout(&addr, Duration::from_millis(100))
+        {
+            backlog.push(stream);
+            assert!(backlog.len() < 10_000, "the backlog never filled up");
+        }
+
+        let client = reqwest::Client::builder()
+            .connect_timeout(Duration::from_millis(100))
+            .build()
+            .unwrap();
+        let metrics = RequestMetrics::new(false, None, false);
+        let params = test_params(&format!("http://{}/", addr));
+
+        let result = make_request_with_retry(&client, &params, 0, &metrics).await;
+
+        assert!(result.is_err());
+        let error_counts = metrics.error_counts.lock().await;
+        assert_eq!(error_counts.get(&ErrorKind::ConnectTimeout), Some(&1));
+        assert_eq!(error_counts.get(&ErrorKind::Timeout), None);
+    }
+
+    #[tokio::test]
+    async fn test_exhausted_retries_are_recorded_as_unrecovered() {
+        let client = reqwest::Client::new();
+        let metrics = RequestMetrics::new(false, None, false);
+        let params = RequestParams {
+            timeout: Duration::from_secs(1),
+            ..test_params("http://127.0.0.1:9")
+        };
+
+        let _ = make_request_with_retry(&client, &params, 1, &metrics).await;
+
+        let outcomes = metrics.retry_outcomes.lock().await;
+        assert_eq!(
+            outcomes.get(&ErrorKind::Connect),
+            Some(&RetryOutcome {
+                retried: 1,
+                recovered: 0
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_compressed_body_is_sent_gzipped() {
+        use flate2::read::GzDecoder;
+        use std::io::Read;
+
+        let body = r#"{"items": ["a", "a", "a", "a", "a", "a", "a", "a"]}"#;
+        let mut server = mockito::Server::new_async().await;
+        let upload = server
+            .mock("POST", "/upload")
+            .match_header("content-encoding", "gzip")
+            .match_request(move |request| {
+                let mut decoded = String::new();
+                GzDecoder::new(request.body().unwrap().as_slice())
+                    .read_to_string(&mut decoded)
+                    .is_ok()
+                    && decoded == body
+            })
+            .create_async()
+            .await;
+        let metrics = RequestMetrics::new(false, None, false);
+        let params = RequestParams {
+            method: Method::POST,
+            body: Some(body.to_string()),
+            compress_body: true,
+            ..test_params(&format!("{}/upload", server.url()))
+        };
+
+        make_request_with_retry(&reqwest::Client::new(), &params, 0, &metrics)
+            .await
+            .unwrap();
+
+        upload.assert_async().await;
+        assert_eq!(
+            metrics.request_body_bytes.load(Ordering::Relaxed),
+            body.len() as u64
+        );
+        assert!(metrics.compressed_body_bytes.load(Ordering::Relaxed) > 0);
+    }
+
+    #[test]
+    fn test_header_expectation() {
+        let expected = HeaderExpectation::parse("Cache-Control:  max-age=3600 ").unwrap();
+        assert_eq!(expected.name, "Cache-Control");
+        assert_eq!(expected.value, "max-age=3600");
+        assert!(HeaderExpectation::parse("no colon").is_err());
+        assert!(HeaderExpectation::parse("bad name: x").is_err());
+
+        let mut headers = HeaderMap::new();
+        assert!(!expected.matches(&headers));
+        headers.append("cache-control", "no-store".parse().unwrap());
+        assert!(!expected.matches(&headers));
+        headers.append("cache-control", "max-age=3600".parse().unwrap());
+        assert!(expected.matches(&headers));
+    }
+
+    #[test]
+    fn test_backoff_delay() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let base = Duration::from_millis(100);
+        let max = Duration::from_millis(1000);
+
+        for _ in 0..100 {
+            // The first retry waits on the base delay, not zero
+            let first = backoff_delay(0, base, max, &mut rng);
+            assert!(
+                first >= Duration::from_millis(50) && first <= base,
+                "{:?}",
+                first
+            );
+            let third = backoff_delay(2, base, max, &mut rng);
+            assert!(third >= Duration::from_millis(200) && third <= Duration::from_millis(400));
+            // Later attempts stop doubling at the cap
+            let capped = backoff_delay(40, base, max, &mut rng);
+            assert!(capped >= Duration::from_millis(500) && capped <= max);
+        }
+        assert_eq!(
+            backoff_delay(3, Duration::ZERO, max, &mut rng),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn test_fail_on() {
+        let (ok, not_found, unavailable) = (
+            StatusCode::OK,
+            StatusCode::NOT_FOUND,
+            StatusCode::SERVICE_UNAVAILABLE,
+        );
+
+        assert_eq!(FailOn::None.failure(unavailable), None);
+        assert_eq!(
+            FailOn::ClientErrors.failure(not_found),
+            Some(ErrorKind::ClientStatus)
+        );
+        assert_eq!(FailOn::ClientErrors.failure(unavailable), None);
+        assert_eq!(FailOn::ServerErrors.failure(not_found), None);
+        assert_eq!(
+            FailOn::ServerErrors.failure(unavailable),
+            Some(ErrorKind::ServerStatus)
+        );
+        assert_eq!(
+            FailOn::AllErrors.failure(not_found),
+            Some(ErrorKind::ClientStatus)
+        );
+        assert_eq!(
+            FailOn::AllErrors.failure(unavailable),
+            Some(ErrorKind::ServerStatus)
+        );
+        assert_eq!(FailOn::AllErrors.failure(ok), None);
+    }
+
+    #[tokio::test]
+    async fn test_success_after_a_retry_is_counted_by_retries() {
+        let mut server = mockito::Server::new_async().await;
+        let unavailable = server
+            .mock("GET", "/recovering")
+            .with_status(503)
+            .expect(1)
+            .create_async()
+            .await;
+        server.mock("GET", "/recovering").create_async().await;
+        let params = RequestParams {
+            retry_base_delay: Duration::from_millis(1),
+            retry_max_delay: Duration::from_millis(1),
+            ..test_params(&format!("{}/recovering", server.url()))
+        };
+        let client = reqwest::Client::new();
+        let metrics = RequestMetrics::new(false, None, false);
+
+        let first = make_request_with_retry(&client, &params, 2, &metrics).await;
+        let second = make_request_with_retry(&client, &params, 2, &metrics).await;
+
+        assert_eq!(first.unwrap().status, StatusCode::OK);
+        assert_eq!(second.unwrap().status, StatusCode::OK);
+        unavailable.assert_async().await;
+        // The first success took one retry, the second none
+        let success_retries = metrics.success_retries.lock().await;
+        assert_eq!(success_retries.get(&1), Some(&1));
+        assert_eq!(success_retries.get(&0), Some(&1));
+        assert_eq!(success_retries.len(), 2);
+        assert_eq!(*metrics.successful_requests.lock().await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_failing_status_is_retried() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/flaky")
+            .with_status(500)
+            .expect(3)
+            .create_async()
+            .await;
+        let mut params = RequestParams {
+            fail_on: FailOn::ServerErrors,
+            ..test_params(&format!("{}/flaky", server.url()))
+        };
+        let client = reqwest::Client::new();
+
+        let metrics = RequestMetrics::new(false, None, false);
+        let result = make_request_with_retry(&client, &params, 2, &metrics).await;
 
         assert!(result.is_err());
+        mock.assert_async().await;
         assert_eq!(*metrics.failed_requests.lock().await, 1);
+        assert_eq!(
+            metrics
+                .error_counts
+                .lock()
+                .await
+                .get(&ErrorKind::ServerStatus),
+            Some(&1)
+        );
+        // Every attempt's response is counted
+        assert_eq!(metrics.status_counts.lock().await.get(&500), Some(&3));
+        // The failure's duration stays out of the success latencies
+        assert_eq!(metrics.request_times.lock().await.count(), 0);
+        assert_eq!(metrics.failure_times.lock().await.count(), 1);
+
+        // Without a policy a 500 is a response like any other
+        params.fail_on = FailOn::None;
+        let metrics = RequestMetrics::new(false, None, false);
+        let result = make_request_with_retry(&client, &params, 2, &metrics).await;
+
+        assert_eq!(result.unwrap().status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(*metrics.successful_requests.lock().await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_header_mismatch_fails_the_request() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/cached")
+            .with_header("cache-control", "no-store")
+            .create_async()
+            .await;
+        let metrics = RequestMetrics::new(false, None, false);
+        let params = RequestParams {
+            expect_headers: Arc::new(vec![HeaderExpectation::parse(
+                "Cache-Control: max-age=3600",
+            )
+            .unwrap()]),
+            ..test_params(&format!("{}/cached", server.url()))
+        };
+
+        let result = make_request_with_retry(&reqwest::Client::new(), &params, 2, &metrics).await;
+
+        assert!(result.is_err());
+        assert_eq!(*metrics.failed_requests.lock().await, 1);
+        assert_eq!(
+            metrics.header_mismatches.lock().await.get("Cache-Control"),
+            Some(&1)
+        );
+        let error_counts = metrics.error_counts.lock().await;
+        assert_eq!(error_counts.get(&ErrorKind::HeaderMismatch), Some(&1));
+    }
+
+    /// A server that challenges NTLM and answers the authenticate message
+    /// with `final_status`.
+    #[cfg(feature = "ntlm")]
+    async fn ntlm_server(final_status: usize) -> (mockito::ServerGuard, mockito::Mock) {
+        use mockito::Matcher;
+
+        let mut challenge = b"NTLMSSP\0".to_vec();
+        challenge.extend(2u32.to_le_bytes());
+        challenge.extend([0; 8]);
+        challenge.extend(0x0008_8207u32.to_le_bytes());
+        challenge.extend([7; 8]);
+        challenge.extend([0; 8]);
+        challenge.extend([0, 0, 0, 0, 48, 0, 0, 0]);
+
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/intranet")
+            .match_header("authorization", Matcher::Missing)
+            .with_status(401)
+            .with_header("www-authenticate", "NTLM")
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/intranet")
+            .match_header("authorization", Matcher::Regex("^NTLM TlRMTVNTUAAB".into()))
+            .with_status(401)
+            .with_header(
+                "www-authenticate",
+                &format!("NTLM {}", crate::secrets::base64_encode(&challenge)),
+            )
+            .create_async()
+            .await;
+        let authenticated = server
+            .mock("GET", "/intranet")
+            .match_header("authorization", Matcher::Regex("^NTLM TlRMTVNTUAAD".into()))
+            .with_status(final_status)
+            .create_async()
+            .await;
+        (server, authenticated)
+    }
+
+    #[cfg(feature = "ntlm")]
+    fn ntlm_params(url: String) -> RequestParams {
+        RequestParams {
+            ntlm: Some(Arc::new(
+                NtlmCredentials::parse("CORP\\svc:secret").unwrap(),
+            )),
+            ..test_params(&url)
+        }
+    }
+
+    #[cfg(feature = "ntlm")]
+    #[tokio::test]
+    async fn test_ntlm_handshake_authenticates() {
+        let (server, authenticated) = ntlm_server(200).await;
+        let metrics = RequestMetrics::new(false, None, false);
+        let params = ntlm_params(format!("{}/intranet", server.url()));
+
+        let response = make_request_with_retry(&reqwest::Client::new(), &params, 0, &metrics)
+            .await
+            .unwrap();
+
+        assert_eq!(response.status, StatusCode::OK);
+        authenticated.assert_async().await;
+    }
+
+    #[cfg(feature = "ntlm")]
+    #[tokio::test]
+    async fn test_rejected_ntlm_credentials_are_not_retried() {
+        let (server, authenticated) = ntlm_server(401).await;
+        let metrics = RequestMetrics::new(false, None, false);
+        let params = ntlm_params(format!("{}/intranet", server.url()));
+
+        let result = make_request_with_retry(&reqwest::Client::new(), &params, 2, &metrics).await;
+
+        assert!(result.is_err());
+        authenticated.assert_async().await;
+        let error_counts = metrics.error_counts.lock().await;
+        assert_eq!(error_counts.get(&ErrorKind::Auth), Some(&1));
+    }
+
+    #[tokio::test]
+    async fn test_unexpected_body_size_fails_the_request() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/stub")
+            .with_body("{}")
+            .create_async()
+            .await;
+        let metrics = RequestMetrics::new(false, None, false);
+        let params = RequestParams {
+            read_body: Some(1024),
+            body_size: Some(BodySizeBounds {
+                min: Some(100),
+                max: None,
+            }),
+            ..test_params(&format!("{}/stub", server.url()))
+        };
+
+        let result = make_request_with_retry(&reqwest::Client::new(), &params, 2, &metrics).await;
+
+        assert!(result.is_err());
+        assert_eq!(*metrics.unexpected_sizes.lock().await, vec![2]);
+        let error_counts = metrics.error_counts.lock().await;
+        assert_eq!(error_counts.get(&ErrorKind::UnexpectedSize), Some(&1));
+
+        let bounds = BodySizeBounds {
+            min: Some(10),
+            max: Some(20),
+        };
+        assert!(bounds.contains(10) && bounds.contains(20));
+        assert!(!bounds.contains(9) && !bounds.contains(21));
+    }
+
+    #[tokio::test]
+    async fn test_body_past_the_read_limit_is_drained_and_counted() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/large")
+            .with_body("x".repeat(5000))
+            .create_async()
+            .await;
+        let metrics = RequestMetrics::new(false, None, false);
+        let mut params = RequestParams {
+            read_body: Some(1024),
+            ..test_params(&format!("{}/large", server.url()))
+        };
+        let client = reqwest::Client::new();
+
+        let response = make_request_with_retry(&client, &params, 0, &metrics)
+            .await
+            .unwrap();
+        assert_eq!(response.body.map(|body| body.len()), Some(1024));
+        assert!(response.truncated);
+        assert_eq!(response.body_bytes, 5000);
+        assert_eq!(metrics.bytes_received.load(Ordering::Relaxed), 5000);
+        assert_eq!(metrics.total_bytes.load(Ordering::Relaxed), 5000);
+
+        // An unread body counts by its Content-Length
+        params.read_body = None;
+        let response = make_request_with_retry(&client, &params, 0, &metrics)
+            .await
+            .unwrap();
+        assert!(response.body.is_none());
+        assert_eq!(metrics.bytes_received.load(Ordering::Relaxed), 5000);
+        assert_eq!(metrics.total_bytes.load(Ordering::Relaxed), 10000);
+    }
+
+    #[test]
+    fn test_attempt_timeout_grows_per_attempt() {
+        let base = Duration::from_secs(2);
+        assert_eq!(attempt_timeout(base, 1.0, 3), base);
+        assert_eq!(attempt_timeout(base, 2.0, 0), base);
+        assert_eq!(attempt_timeout(base, 2.0, 2), Duration::from_secs(8));
+        assert_eq!(attempt_timeout(base, 1.5, 1), Duration::from_secs(3));
+    }
+
+    #[tokio::test]
+    async fn test_schema_violation_fails_the_request() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/user")
+            .with_body(r#"{"id": "7"}"#)
+            .create_async()
+            .await;
+        let schema = ResponseSchema::compile(serde_json::json!({
+            "type": "object",
+            "properties": { "id": { "type": "integer" } }
+        }))
+        .unwrap();
+        let metrics = RequestMetrics::new(false, None, false);
+        let params = RequestParams {
+            read_body: Some(1024),
+            response_schema: Some(Arc::new(schema)),
+            ..test_params(&format!("{}/user", server.url()))
+        };
+
+        let result = make_request_with_retry(&reqwest::Client::new(), &params, 2, &metrics).await;
+
+        assert!(result.is_err());
+        let error_counts = metrics.error_counts.lock().await;
+        assert_eq!(error_counts.get(&ErrorKind::SchemaViolation), Some(&1));
+        assert_eq!(
+            *metrics.schema_violations.lock().await,
+            vec!["$.id: expected integer, got string".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_handshake_is_a_tls_error() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // Answer the TLS ClientHello in plain HTTP, as a server without TLS would
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut hello = [0u8; 1024];
+            let _ = socket.read(&mut hello).await;
+            let _ = socket
+                .write_all(b"HTTP/1.1 400 Bad Request\r\ncontent-length: 0\r\n\r\n")
+                .await;
+        });
+        let metrics = RequestMetrics::new(false, None, false);
+        let params = test_params(&format!("https://{}/", addr));
+
+        let result = make_request_with_retry(&reqwest::Client::new(), &params, 0, &metrics).await;
+
+        assert!(result.is_err());
+        let error_counts = metrics.error_counts.lock().await;
+        assert_eq!(error_counts.get(&ErrorKind::Tls), Some(&1));
+    }
+
+    #[tokio::test]
+    async fn test_absorb_adds_counts_and_latencies() {
+        let overall = RequestMetrics::new(false, None, false);
+        for latency in [10, 30] {
+            let phase = RequestMetrics::new(true, None, false);
+            *phase.successful_requests.lock().await += 2;
+            *phase.failed_requests.lock().await += 1;
+            phase
+                .request_times
+                .lock()
+                .await
+                .record(Duration::from_millis(latency));
+            phase
+                .error_counts
+                .lock()
+                .await
+                .insert(ErrorKind::Timeout, 1);
+            phase.bytes_received.fetch_add(100, Ordering::Relaxed);
+            overall.absorb(&phase).await;
+        }
+
+        assert_eq!(*overall.successful_requests.lock().await, 4);
+        assert_eq!(*overall.failed_requests.lock().await, 2);
+        let times = overall.request_times.lock().await;
+        assert_eq!(times.count(), 2);
+        assert!(times.raw().is_none());
+        assert_eq!(overall.error_counts.lock().await[&ErrorKind::Timeout], 2);
+        assert_eq!(overall.bytes_received.load(Ordering::Relaxed), 200);
+    }
+
+    #[tokio::test]
+    async fn test_reset_mid_body_is_an_incomplete_response() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // Promise 100 bytes, send 5, then drop the connection
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 1024];
+            let _ = socket.read(&mut request).await;
+            let _ = socket
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 100\r\n\r\nhello")
+                .await;
+        });
+        let metrics = RequestMetrics::new(false, None, false);
+        let params = RequestParams {
+            read_body: Some(1024),
+            ..test_params(&format!("http://{}/", addr))
+        };
+
+        let result = make_request_with_retry(&reqwest::Client::new(), &params, 0, &metrics).await;
+
+        assert!(result.is_err());
+        let error_counts = metrics.error_counts.lock().await;
+        assert_eq!(error_counts.get(&ErrorKind::IncompleteResponse), Some(&1));
+    }
+
+    #[tokio::test]
+    async fn test_tracked_bodies_are_grouped_by_status_and_hash() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/orders")
+            .with_status(201)
+            .with_body("order 1")
+            .create_async()
+            .await;
+        let mut metrics = RequestMetrics::new(false, None, false);
+        metrics.track_bodies();
+        let params = RequestParams {
+            method: Method::POST,
+            read_body: Some(1024),
+            ..test_params(&format!("{}/orders", server.url()))
+        };
+
+        let client = reqwest::Client::new();
+        for _ in 0..3 {
+            make_request_with_retry(&client, &params, 0, &metrics)
+                .await
+                .unwrap();
+        }
+
+        let body_hashes = metrics.body_hashes.as_ref().unwrap().lock().await;
+        assert_eq!(body_hashes.len(), 1);
+        assert_eq!(
+            body_hashes
+                .iter()
+                .next()
+                .map(|(&(status, _), &n)| (status, n)),
+            Some((201, 3))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_correlation_header_is_sent() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/traced")
+            .match_header("x-request-id", "run-00000001")
+            .create_async()
+            .await;
+        let params = RequestParams {
+            correlation: Some(("X-Request-ID".to_string(), "run-00000001".to_string())),
+            ..test_params(&format!("{}/traced", server.url()))
+        };
+
+        let result = make_request_with_retry(
+            &reqwest::Client::new(),
+            &params,
+            0,
+            &RequestMetrics::new(false, None, false),
+        )
+        .await;
+
+        assert!(result.is_ok());
+        mock.assert_async().await;
     }
 }
@@ -0,0 +1,143 @@
+use std::net::{IpAddr, SocketAddr};
+use url::Url;
+
+use crate::scenario::Target;
+
+/// A `--resolve HOST:PORT:ADDR` entry: connect to `addr` whenever a request
+/// names `host`, bypassing DNS.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolveOverride {
+    pub host: String,
+    pub addr: SocketAddr,
+}
+
+impl ResolveOverride {
+    /// Parse `HOST:PORT:ADDR`, where an IPv6 address may be bracketed.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid --resolve '{}': expected HOST:PORT:ADDR", spec);
+        let mut parts = spec.splitn(3, ':');
+        let (Some(host), Some(port), Some(addr)) = (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid());
+        };
+        if host.is_empty() {
+            return Err(invalid());
+        }
+        let port: u16 = port.parse().map_err(|_| invalid())?;
+        let ip: IpAddr = addr
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse()
+            .map_err(|_| invalid())?;
+
+        Ok(Self {
+            host: host.to_ascii_lowercase(),
+            addr: SocketAddr::new(ip, port),
+        })
+    }
+
+    /// The entry in the form accepted by [`ResolveOverride::parse`].
+    pub fn to_spec(&self) -> String {
+        match self.addr.ip() {
+            IpAddr::V4(ip) => format!("{}:{}:{}", self.host, self.addr.port(), ip),
+            IpAddr::V6(ip) => format!("{}:{}:[{}]", self.host, self.addr.port(), ip),
+        }
+    }
+}
+
+/// The address requests to `host:port` connect to: a matching override if
+/// there is one, otherwise the first address DNS returns.
+pub async fn connect_address(
+    host: &str,
+    port: u16,
+    overrides: &[ResolveOverride],
+) -> Result<SocketAddr, String> {
+    if let Some(entry) = overrides
+        .iter()
+        .find(|entry| entry.host.eq_ignore_ascii_case(host) && entry.addr.port() == port)
+    {
+        return Ok(entry.addr);
+    }
+
+    tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|e| format!("Failed to resolve {}: {}", host, e))?
+        .next()
+        .ok_or_else(|| format!("Failed to resolve {}: no addresses", host))
+}
+
+/// Point `target` at `sni` so the TLS handshake names it, while its `Host`
+/// header keeps naming the original host. The caller resolves `sni` to the
+/// original host's address, so the connection goes where it did before.
+pub fn apply_sni(target: &mut Target, sni: &str) -> Result<(), String> {
+    let mut url =
+        Url::parse(&target.url).map_err(|e| format!("Invalid URL {}: {}", target.url, e))?;
+    if url.scheme() != "https" {
+        return Err(format!("--sni needs an https URL, got {}", target.url));
+    }
+    let host = match (url.host_str(), url.port()) {
+        (Some(host), Some(port)) => format!("{}:{}", host, port),
+        (Some(host), None) => host.to_string(),
+        (None, _) => return Err(format!("URL has no host: {}", target.url)),
+    };
+    url.set_host(Some(sni))
+        .map_err(|e| format!("Invalid --sni {}: {}", sni, e))?;
+
+    target.url = url.to_string();
+    if !target
+        .headers
+        .iter()
+        .any(|(name, _)| name.eq_ignore_ascii_case("host"))
+    {
+        target.headers.push(("Host".to_string(), host));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_resolve() {
+        let entry = ResolveOverride::parse("API.example.com:443:10.0.0.7").unwrap();
+        assert_eq!(entry.host, "api.example.com");
+        assert_eq!(entry.addr, "10.0.0.7:443".parse().unwrap());
+        assert_eq!(entry.to_spec(), "api.example.com:443:10.0.0.7");
+
+        let v6 = ResolveOverride::parse("example.com:8443:[::1]").unwrap();
+        assert_eq!(v6.addr, "[::1]:8443".parse().unwrap());
+        assert_eq!(
+            ResolveOverride::parse(&v6.to_spec()).unwrap(),
+            v6,
+            "to_spec round-trips"
+        );
+
+        assert!(ResolveOverride::parse("example.com:443").is_err());
+        assert!(ResolveOverride::parse("example.com:https:10.0.0.7").is_err());
+        assert!(ResolveOverride::parse(":443:10.0.0.7").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_connect_address_prefers_override() {
+        let overrides = vec![ResolveOverride::parse("example.com:443:10.0.0.7").unwrap()];
+        let addr = connect_address("EXAMPLE.com", 443, &overrides).await;
+        assert_eq!(addr, Ok("10.0.0.7:443".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_apply_sni() {
+        let mut target = Target::new("https://node1.internal:8443/health?x=1", Duration::ZERO);
+        apply_sni(&mut target, "api.example.com").unwrap();
+
+        assert_eq!(target.url, "https://api.example.com:8443/health?x=1");
+        assert_eq!(
+            target.headers,
+            vec![("Host".to_string(), "node1.internal:8443".to_string())]
+        );
+
+        let mut plain = Target::new("http://example.com/", Duration::ZERO);
+        assert!(apply_sni(&mut plain, "api.example.com").is_err());
+    }
+}
@@ -0,0 +1,1609 @@
+use colored::*;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use reqwest::Method;
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::signal;
+use tokio::sync::{watch, Mutex};
+use tokio::time::{self, interval};
+
+use crate::auth::AuthRefresher;
+use crate::bandwidth::ByteRateLimiter;
+use crate::chain::run_chain;
+use crate::correlation::CorrelationIds;
+use crate::dns::DnsTimings;
+#[cfg(feature = "ntlm")]
+use crate::ntlm::NtlmCredentials;
+use crate::output::{self, logln};
+use crate::records::RecordLog;
+use crate::report::{compute_report, format_oneline, DEFAULT_PERCENTILES};
+use crate::requests::{
+    make_request_with_retry, method_takes_body, BodySizeBounds, FailOn, HeaderExpectation,
+    RequestMetrics, RequestParams,
+};
+use crate::scenario::{Target, TargetWeights};
+use crate::schema::ResponseSchema;
+use crate::sparkline::RateHistory;
+use crate::think::ThinkTime;
+
+/// Response body read limit used when none is configured: 10MB.
+pub const DEFAULT_MAX_BODY_BYTES: usize = 10 * 1024 * 1024;
+
+/// Seconds of throughput shown in the status line sparkline.
+const SPARKLINE_SECONDS: usize = 20;
+
+/// Requests spawned at once before the dispatch loop yields to the
+/// scheduler, when none is configured.
+pub const DEFAULT_SPAWN_BATCH: u32 = 1000;
+
+/// How long a stopped run waits for in-flight requests when none is configured.
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A --gap dispatch counts as on time within this much of its scheduled
+/// start, or a tenth of the gap if that's longer.
+const ON_TIME_TOLERANCE: Duration = Duration::from_millis(2);
+
+/// How often the `TIDE_ABORT` file is checked for.
+const ABORT_FILE_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Width of the --requests progress bar, in characters.
+const PROGRESS_BAR_WIDTH: usize = 30;
+
+/// Time limit of a --requests run, which ends by count; it only keeps
+/// deadline arithmetic finite.
+const REQUEST_MODE_TIME_LIMIT: Duration = Duration::from_secs(365 * 24 * 3600);
+
+/// How often the requests in flight are sampled.
+const IN_FLIGHT_SAMPLE_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How often the bytes read so far are checked against --max-bytes.
+const BYTE_CAP_CHECK_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Everything that shapes the load of a single run.
+#[derive(Clone)]
+pub struct LoadPlan {
+    pub targets: Arc<Vec<Target>>,
+    /// Run every target in order per virtual user instead of cycling through them
+    pub chain: bool,
+    /// Pick each request's target at random by these weights instead of
+    /// cycling through them, for a weighted --url mix
+    pub target_weights: Option<TargetWeights>,
+    pub methods: Vec<Method>,
+    pub body: Option<String>,
+    /// Gzip request bodies and send them with `Content-Encoding: gzip`
+    pub compress_request: bool,
+    /// Response statuses that fail a request
+    pub fail_on: FailOn,
+    pub concurrency: u32,
+    /// Ramp each second's batch linearly from `concurrency` to this many
+    /// requests over the duration
+    pub ramp_to: Option<u32>,
+    /// Scale concurrency linearly from 1 to its full level over this long at
+    /// the start of the run; zero starts at full concurrency
+    pub ramp_up: Duration,
+    /// Spawn at most this many requests or workers before yielding to the
+    /// scheduler, so extreme concurrency doesn't flood it all at once
+    pub spawn_batch: u32,
+    pub duration: Duration,
+    /// End the run after this many requests instead of after `duration`
+    pub request_limit: Option<u32>,
+    pub retries: u32,
+    /// Factor each retry's timeout grows by over the previous attempt's
+    pub timeout_growth: f64,
+    /// Backoff before the first retry, doubled for each later one
+    pub retry_base_delay: Duration,
+    /// Longest backoff between retries
+    pub retry_max_delay: Duration,
+    /// Target time T for the Apdex score, when one is reported
+    pub apdex_threshold: Option<Duration>,
+    /// Flag every request slower than this as an SLA violation
+    pub hard_deadline: Option<Duration>,
+    pub raw_latencies: bool,
+    pub max_samples: Option<usize>,
+    /// Start-to-start delay between requests on each persistent worker. When
+    /// set, `concurrency` workers loop for the whole run instead of a batch of
+    /// requests being launched every second.
+    pub gap: Option<Duration>,
+    /// Pause after each request on a persistent worker before its next one.
+    /// Like `gap`, it makes `concurrency` workers loop for the whole run.
+    pub think_time: Option<ThinkTime>,
+    /// Requests started per second, however many are still in flight. When
+    /// set, requests arrive at this steady rate instead of in batches of
+    /// `concurrency`.
+    pub rps: Option<f64>,
+    /// Shuffle header order on every request
+    pub randomize_headers: bool,
+    /// Read response bodies up to this many bytes; `None` leaves them unread
+    pub read_body: Option<usize>,
+    /// Print elapsed/remaining time every second. Off for all but one of
+    /// several runs going at once.
+    pub show_progress: bool,
+    /// Keep every completion's finish time for time-series output
+    pub timeseries: bool,
+    /// Print a one-line summary of the results so far this often, alongside
+    /// the status line
+    pub summary_interval: Option<Duration>,
+    /// Keeps the `Authorization` header fresh from an external command
+    pub auth: Option<Arc<AuthRefresher>>,
+    /// Also refresh the token on this schedule, not only after a 401
+    pub auth_refresh_interval: Option<Duration>,
+    /// Tags every request with a unique ID header
+    pub correlation: Option<Arc<CorrelationIds>>,
+    /// Headers every response must carry to count as successful
+    pub expect_headers: Arc<Vec<HeaderExpectation>>,
+    /// Schema that 2xx response bodies must match
+    pub response_schema: Option<Arc<ResponseSchema>>,
+    /// Sizes every response body must fall between
+    pub body_size: Option<BodySizeBounds>,
+    /// Break each success line's duration down into time to first byte and body
+    pub timing_breakdown: bool,
+    /// How long a stopped run waits for in-flight requests before aborting them
+    pub drain_timeout: Duration,
+    /// Group response bodies by hash to check that repeated requests agree
+    pub idempotency_check: bool,
+    /// Paces body reads for --max-bandwidth, shared by all workers and by
+    /// runs going on at the same time
+    pub bandwidth: Option<Arc<ByteRateLimiter>>,
+    /// Stop the run once more than this many body bytes were read in total
+    pub max_bytes: Option<u64>,
+    /// Adapt each second's batch to 429 and 503 responses, never going below
+    /// this many requests or above `concurrency`
+    pub backpressure_floor: Option<u32>,
+    /// Count requests by URL, outcome and latency bucket for --folded
+    pub folded: bool,
+    /// Keep the run going past its duration until every target has completed
+    /// this many requests
+    pub min_per_url: Option<u32>,
+    /// Lookup times of the run's clients, when --dns-timing built them with a
+    /// timed resolver
+    pub dns_timings: Option<Arc<DnsTimings>>,
+    /// Every finished request is written here as a CSV row, for --csv
+    pub records: Option<Arc<RecordLog>>,
+    /// Compare client latency with the server time of Server-Timing headers
+    pub server_timing: bool,
+    /// Give each request a client of its own for as long as it runs, so a
+    /// connection authenticated with NTLM stays with one worker
+    pub exclusive_clients: bool,
+    /// Answer NTLM and Negotiate challenges with these credentials
+    #[cfg(feature = "ntlm")]
+    pub ntlm: Option<Arc<NtlmCredentials>>,
+}
+
+impl LoadPlan {
+    /// How long the run may last at most: its duration, or twice that when
+    /// --min-per-url may extend it. A --requests run has no time limit.
+    pub fn max_duration(&self) -> Duration {
+        if self.request_limit.is_some() {
+            return REQUEST_MODE_TIME_LIMIT;
+        }
+        match self.min_per_url {
+            Some(_) => self.duration * 2,
+            None => self.duration,
+        }
+    }
+
+    /// Requests to launch in the batch that starts `elapsed` into the run:
+    /// `concurrency`, or on a ramp the level reached by then. During the
+    /// ramp-up the batch grows from 1 towards that level.
+    pub fn batch_size(&self, elapsed: Duration) -> u32 {
+        let full = match self.ramp_to {
+            Some(end) => {
+                let progress = (elapsed.as_secs_f64() / self.duration.as_secs_f64()).min(1.0);
+                let start = self.concurrency as f64;
+                (start + (end as f64 - start) * progress).round() as u32
+            }
+            None => self.concurrency,
+        };
+        if elapsed >= self.ramp_up {
+            return full;
+        }
+        let progress = elapsed.as_secs_f64() / self.ramp_up.as_secs_f64();
+        (1.0 + full.saturating_sub(1) as f64 * progress).round() as u32
+    }
+
+    /// The least average time between request starts on each persistent
+    /// worker: the gap, or the mean think time when that's longer. `None`
+    /// for runs without persistent workers.
+    pub fn worker_pacing(&self) -> Option<Duration> {
+        match (self.gap, self.think_time) {
+            (gap, Some(think)) => Some(gap.unwrap_or_default().max(think.mean())),
+            (gap, None) => gap,
+        }
+    }
+
+    /// How long into the run persistent worker `index` starts: spread evenly
+    /// over the ramp-up, so the first starts at once.
+    pub fn worker_start(&self, index: u32) -> Duration {
+        self.ramp_up
+            .mul_f64(index as f64 / self.concurrency.max(1) as f64)
+    }
+
+    /// A fresh correlation header for the next request, if IDs are enabled.
+    pub fn correlation_header(&self) -> Option<(String, String)> {
+        self.correlation
+            .as_ref()
+            .map(|ids| (ids.header.clone(), ids.next_id()))
+    }
+}
+
+/// The raw results of a run, ready to be turned into a report.
+pub struct RunOutcome {
+    pub metrics: RequestMetrics,
+    pub total_requests: u32,
+    pub elapsed: Duration,
+}
+
+/// Presses of Ctrl-C within this window of each other count towards a forced quit.
+const FORCE_QUIT_WINDOW: Duration = Duration::from_secs(5);
+
+/// What a Ctrl-C press should do.
+#[derive(Debug, PartialEq)]
+enum SignalAction {
+    /// Stop the run gracefully and still print the report
+    Drain,
+    /// Exit immediately
+    ForceQuit,
+}
+
+/// Counts Ctrl-C presses so that an accidental one only stops the run, while
+/// pressing repeatedly in quick succession still quits outright.
+struct SignalCounter {
+    force_quit_presses: u32,
+    presses: u32,
+    first_press: Option<Instant>,
+}
+
+impl SignalCounter {
+    fn new(force_quit_presses: u32) -> Self {
+        Self {
+            force_quit_presses,
+            presses: 0,
+            first_press: None,
+        }
+    }
+
+    fn press(&mut self, now: Instant) -> SignalAction {
+        match self.first_press {
+            Some(first) if now.duration_since(first) <= FORCE_QUIT_WINDOW => self.presses += 1,
+            _ => {
+                self.first_press = Some(now);
+                self.presses = 1;
+            }
+        }
+
+        if self.presses >= self.force_quit_presses {
+            SignalAction::ForceQuit
+        } else {
+            SignalAction::Drain
+        }
+    }
+}
+
+/// Listen for Ctrl-C in the background. The returned receiver flips to `true`
+/// once the user asks to stop, and can be shared by consecutive runs. Pressing
+/// Ctrl-C `force_quit_presses` times within a few seconds exits immediately.
+/// With an `abort_file`, the file appearing stops the run the same way.
+pub fn shutdown_channel(
+    force_quit_presses: u32,
+    abort_file: Option<PathBuf>,
+) -> watch::Receiver<bool> {
+    let (sender, receiver) = watch::channel(false);
+    let sender = Arc::new(sender);
+
+    if let Some(path) = abort_file {
+        tokio::spawn(watch_abort_file(path, sender.clone()));
+    }
+
+    tokio::spawn(async move {
+        let mut counter = SignalCounter::new(force_quit_presses);
+        loop {
+            if let Err(e) = signal::ctrl_c().await {
+                eprintln!("{}Shutdown signal error: {}{}", "".red(), e, "".clear());
+                break;
+            }
+
+            match counter.press(Instant::now()) {
+                SignalAction::ForceQuit => {
+                    eprintln!("{}Forced quit{}", "".red(), "".clear());
+                    std::process::exit(130);
+                }
+                SignalAction::Drain => {
+                    let remaining = force_quit_presses - counter.presses;
+                    eprintln!(
+                        "{}Stopping the run... press Ctrl-C {} more time(s) to force quit{}",
+                        "".yellow(),
+                        remaining,
+                        "".clear()
+                    );
+                    let _ = sender.send(true);
+                }
+            }
+        }
+        // Keep the sender alive so receivers don't see the channel close
+        sender.closed().await;
+    });
+
+    receiver
+}
+
+/// Stop the run once `path` exists, checking every [`ABORT_FILE_POLL_INTERVAL`],
+/// for environments where sending a signal is awkward.
+async fn watch_abort_file(path: PathBuf, sender: Arc<watch::Sender<bool>>) {
+    let mut poll = interval(ABORT_FILE_POLL_INTERVAL);
+    loop {
+        poll.tick().await;
+        if sender.is_closed() {
+            return;
+        }
+        if tokio::fs::try_exists(&path).await.unwrap_or(false) {
+            eprintln!(
+                "{}Stopping the run: abort file {} appeared{}",
+                "".yellow(),
+                path.display(),
+                "".clear()
+            );
+            let _ = sender.send(true);
+            return;
+        }
+    }
+}
+
+pub fn is_shutdown(shutdown: &watch::Receiver<bool>) -> bool {
+    *shutdown.borrow()
+}
+
+/// Shared state handed to every dispatched request or chain.
+#[derive(Clone)]
+struct Dispatcher {
+    /// Equivalent clients that differ only in how they write headers; each
+    /// request picks one at random.
+    clients: Arc<Vec<reqwest::Client>>,
+    /// With exclusive clients, those no request is using right now
+    idle_clients: Option<Arc<std::sync::Mutex<Vec<reqwest::Client>>>>,
+    metrics: RequestMetrics,
+    total_requests: Arc<Mutex<u32>>,
+    sequence: Arc<AtomicUsize>,
+    /// Requests claimed against --requests so far
+    claimed: Arc<AtomicU32>,
+    /// Dispatches that have started but not finished
+    in_flight: Arc<AtomicU32>,
+    /// Flips to `true` when the shutdown drain gives up on in-flight requests
+    abort: watch::Receiver<bool>,
+}
+
+/// A client taken from the idle list for one dispatch. It goes back on drop,
+/// so an aborted request returns it too.
+struct ClientLease {
+    client: reqwest::Client,
+    idle: Arc<std::sync::Mutex<Vec<reqwest::Client>>>,
+}
+
+impl Drop for ClientLease {
+    fn drop(&mut self) {
+        let mut idle = self.idle.lock().unwrap_or_else(|e| e.into_inner());
+        idle.push(self.client.clone());
+    }
+}
+
+impl Dispatcher {
+    /// An idle client for exclusive use, if exclusive clients are on and one
+    /// is free. Otherwise the request shares a client picked at random.
+    fn lease_client(&self) -> Option<ClientLease> {
+        let idle = self.idle_clients.as_ref()?;
+        let client = idle.lock().unwrap_or_else(|e| e.into_inner()).pop()?;
+        Some(ClientLease {
+            client,
+            idle: idle.clone(),
+        })
+    }
+
+    /// Take one of the run's requests before dispatching it; `false` once a
+    /// --requests run has handed out all of them.
+    fn claim(&self, plan: &LoadPlan) -> bool {
+        match plan.request_limit {
+            Some(limit) => self.claimed.fetch_add(1, Ordering::SeqCst) < limit,
+            None => true,
+        }
+    }
+
+    /// Whether a --requests run has handed out all of its requests.
+    fn exhausted(&self, plan: &LoadPlan) -> bool {
+        plan.request_limit
+            .is_some_and(|limit| self.claimed.load(Ordering::SeqCst) >= limit)
+    }
+
+    /// Send the next request, unless the run aborts it first. An aborted
+    /// request is counted as such instead of as a success or failure.
+    async fn dispatch(&self, plan: &LoadPlan) {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        let mut abort = self.abort.clone();
+        let aborted = async {
+            if abort.wait_for(|abort| *abort).await.is_err() {
+                // The run is over; nothing will abort this request any more
+                std::future::pending::<()>().await;
+            }
+        };
+        tokio::select! {
+            _ = self.send(plan) => {},
+            _ = aborted => {
+                *self.metrics.aborted_requests.lock().await += 1;
+            }
+        }
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Send the next request in the plan's rotation, or run the whole chain for
+    /// one virtual user in chain mode.
+    async fn send(&self, plan: &LoadPlan) {
+        let lease = self.lease_client();
+        let client = match &lease {
+            Some(lease) => &lease.client,
+            None => &self.clients[rand::rng().random_range(0..self.clients.len())],
+        };
+
+        if plan.chain {
+            run_chain(client, plan, &self.metrics, &self.total_requests).await;
+            return;
+        }
+
+        let sequence = self.sequence.fetch_add(1, Ordering::Relaxed);
+        let index = match &plan.target_weights {
+            Some(weights) => weights.pick(),
+            None => sequence % plan.targets.len(),
+        };
+        let target = &plan.targets[index];
+        let method = target
+            .method
+            .clone()
+            .unwrap_or_else(|| plan.methods[sequence % plan.methods.len()].clone());
+        // Only attach the body for verbs that semantically take one
+        let body = target
+            .body
+            .clone()
+            .or_else(|| plan.body.clone())
+            .filter(|_| method_takes_body(&method));
+        let mut headers = target.headers.clone();
+        if plan.randomize_headers {
+            shuffle_headers(&mut headers, &mut rand::rng());
+        }
+        let params = RequestParams {
+            method,
+            url: target.url.clone(),
+            body,
+            headers,
+            timeout: target.timeout,
+            timeout_growth: plan.timeout_growth,
+            retry_base_delay: plan.retry_base_delay,
+            retry_max_delay: plan.retry_max_delay,
+            read_body: plan.read_body,
+            auth: plan.auth.clone(),
+            correlation: plan.correlation_header(),
+            expect_headers: plan.expect_headers.clone(),
+            response_schema: plan.response_schema.clone(),
+            body_size: plan.body_size,
+            timing_breakdown: plan.timing_breakdown,
+            compress_body: plan.compress_request,
+            fail_on: plan.fail_on,
+            #[cfg(feature = "ntlm")]
+            ntlm: plan.ntlm.clone(),
+        };
+
+        {
+            let mut total = self.total_requests.lock().await;
+            *total += 1;
+        }
+        let result = make_request_with_retry(client, &params, plan.retries, &self.metrics).await;
+        if let Some(samples) = &self.metrics.url_samples {
+            samples.lock().await.record(index);
+        }
+        if let Some(stats) = &self.metrics.url_stats {
+            if let Some(stats) = stats.lock().await.get_mut(index) {
+                match &result {
+                    Ok(response) => stats.latency.record(response.latency),
+                    Err(_) => stats.failed += 1,
+                }
+            }
+        }
+
+        if let Err(e) = result {
+            eprintln!("{}Request failed: {}{}", "".red(), e, "".clear());
+        }
+    }
+}
+
+/// Send the headers in a random order for --randomize-headers, so a server
+/// that keys on header order sees no fixed fingerprint.
+fn shuffle_headers(headers: &mut [(String, String)], rng: &mut impl Rng) {
+    headers.shuffle(rng);
+}
+
+/// Apply `plan` until its duration elapses or shutdown is requested. Requests
+/// are spread at random over `clients`, which must not be empty.
+pub async fn run_load(
+    clients: Vec<reqwest::Client>,
+    plan: Arc<LoadPlan>,
+    shutdown: &watch::Receiver<bool>,
+) -> RunOutcome {
+    let mut metrics = RequestMetrics::new(plan.raw_latencies, plan.max_samples, plan.timeseries);
+    metrics.track_in_flight();
+    if plan.idempotency_check {
+        metrics.track_bodies();
+    }
+    if plan.chain {
+        metrics.track_journeys(&plan.targets);
+    }
+    if let Some(threshold) = plan.apdex_threshold {
+        metrics.track_apdex(threshold);
+    }
+    if let Some(deadline) = plan.hard_deadline {
+        metrics.track_deadline(deadline);
+    }
+    if let Some(limiter) = &plan.bandwidth {
+        metrics.limit_bandwidth(limiter.clone());
+    }
+    if let Some(cap) = plan.max_bytes {
+        metrics.cap_bytes(cap);
+    }
+    if let Some(timings) = &plan.dns_timings {
+        metrics.track_dns(timings.clone());
+    }
+    if let Some(log) = &plan.records {
+        metrics.track_records(log.clone());
+    }
+    if let Some(floor) = plan.backpressure_floor {
+        metrics.track_backpressure(floor, plan.concurrency);
+    }
+    if plan.folded {
+        metrics.track_folded();
+    }
+    if plan.server_timing {
+        metrics.track_server_timing();
+    }
+    if plan.concurrency.max(plan.ramp_to.unwrap_or(0)) > plan.spawn_batch {
+        metrics.track_spawn_stalls(plan.spawn_batch);
+    }
+    if let Some(minimum) = plan.min_per_url {
+        metrics.track_url_samples(&plan.targets, minimum);
+    }
+    if let Some(weights) = &plan.target_weights {
+        metrics.track_url_stats(&plan.targets, weights.weights());
+    }
+    // Think time makes the rate depend on latency, so there's none to check
+    if let (Some(gap), None) = (plan.gap, plan.think_time) {
+        metrics.track_rate(
+            plan.concurrency as f64 / gap.as_secs_f64(),
+            ON_TIME_TOLERANCE.max(gap / 10),
+        );
+    }
+    if let Some(rps) = plan.rps {
+        let interval = Duration::from_secs_f64(1.0 / rps);
+        metrics.track_rate(rps, ON_TIME_TOLERANCE.max(interval / 10));
+    }
+    let (abort_sender, abort_receiver) = watch::channel(false);
+    let idle_clients = plan
+        .exclusive_clients
+        .then(|| Arc::new(std::sync::Mutex::new(clients.clone())));
+    let dispatcher = Dispatcher {
+        clients: Arc::new(clients),
+        idle_clients,
+        metrics,
+        total_requests: Arc::new(Mutex::new(0u32)),
+        sequence: Arc::new(AtomicUsize::new(0)),
+        claimed: Arc::new(AtomicU32::new(0)),
+        in_flight: Arc::new(AtomicU32::new(0)),
+        abort: abort_receiver,
+    };
+    let start_time = Instant::now();
+
+    let refresher = match (&plan.auth, plan.auth_refresh_interval) {
+        (Some(auth), Some(interval)) => {
+            let auth = auth.clone();
+            Some(tokio::spawn(
+                async move { auth.refresh_every(interval).await },
+            ))
+        }
+        _ => None,
+    };
+
+    let snapshots = plan
+        .summary_interval
+        .filter(|_| plan.show_progress)
+        .map(|every| {
+            let metrics = dispatcher.metrics.clone();
+            let total_requests = dispatcher.total_requests.clone();
+            let concurrency = plan.concurrency;
+            tokio::spawn(async move {
+                let first = time::Instant::from_std(start_time + every);
+                let mut ticker = time::interval_at(first, every);
+                loop {
+                    ticker.tick().await;
+                    let total = *total_requests.lock().await;
+                    let line = snapshot(&metrics, total, concurrency, start_time.elapsed()).await;
+                    logln!("\n{}", line.bold());
+                }
+            })
+        });
+
+    let sampler = dispatcher.metrics.in_flight.clone().map(|samples| {
+        let in_flight = dispatcher.in_flight.clone();
+        tokio::spawn(async move {
+            let mut ticker = interval(IN_FLIGHT_SAMPLE_INTERVAL);
+            loop {
+                ticker.tick().await;
+                let current = in_flight.load(Ordering::SeqCst);
+                samples.lock().await.record(current);
+            }
+        })
+    });
+
+    let execution = async {
+        match (plan.rps, plan.worker_pacing()) {
+            (Some(rps), _) => run_open_loop(&dispatcher, &plan, rps, start_time, shutdown).await,
+            (None, Some(_)) => {
+                let gap = plan.gap.unwrap_or_default();
+                run_persistent_workers(&dispatcher, &plan, gap, start_time, shutdown).await
+            }
+            (None, None) => run_ticks(&dispatcher, &plan, start_time).await,
+        }
+    };
+
+    // Run with graceful shutdown
+    let mut shutdown = shutdown.clone();
+    let capped = tokio::select! {
+        _ = execution => false,
+        _ = shutdown.wait_for(|stop| *stop) => false,
+        _ = byte_cap_reached(&dispatcher.metrics) => true,
+    };
+    output::end_status();
+    // The drain only winds the run down, so it isn't sampled
+    if let Some(sampler) = sampler {
+        sampler.abort();
+    }
+
+    // A stopped run leaves requests in flight; give them a bounded time to
+    // finish. Past the byte cap every byte counts, so they're aborted at once.
+    let drain_timeout = if capped {
+        eprintln!(
+            "{}Stopping the run: more than {} bytes read (--max-bytes){}",
+            "".yellow(),
+            plan.max_bytes.unwrap_or_default(),
+            "".clear()
+        );
+        Duration::ZERO
+    } else {
+        plan.drain_timeout
+    };
+    drain(&dispatcher.in_flight, drain_timeout, &abort_sender).await;
+
+    if let Some(refresher) = refresher {
+        refresher.abort();
+    }
+    if let Some(snapshots) = snapshots {
+        snapshots.abort();
+    }
+
+    let elapsed = start_time.elapsed();
+    let total_requests = *dispatcher.total_requests.lock().await;
+
+    RunOutcome {
+        metrics: dispatcher.metrics,
+        total_requests,
+        elapsed,
+    }
+}
+
+/// A one-line summary of the results `elapsed` into a run, for
+/// --summary-interval. Rates cover the run so far, not just the last interval.
+async fn snapshot(
+    metrics: &RequestMetrics,
+    total_requests: u32,
+    concurrency: u32,
+    elapsed: Duration,
+) -> String {
+    let report = compute_report(
+        "",
+        concurrency,
+        elapsed,
+        total_requests,
+        metrics,
+        None,
+        &DEFAULT_PERCENTILES,
+    )
+    .await;
+    format!(
+        "Summary at {}s: {}",
+        elapsed.as_secs(),
+        format_oneline(&report)
+    )
+}
+
+/// Resolves once the run has read more body bytes than --max-bytes allows,
+/// and never without a cap.
+async fn byte_cap_reached(metrics: &RequestMetrics) {
+    if metrics.byte_cap.is_none() {
+        return std::future::pending().await;
+    }
+    let mut check = interval(BYTE_CAP_CHECK_INTERVAL);
+    while !metrics.byte_cap_exceeded() {
+        check.tick().await;
+    }
+}
+
+/// Wait up to `timeout` for in-flight requests to finish, then abort the rest
+/// and wait for them to record that they were aborted.
+async fn drain(in_flight: &AtomicU32, timeout: Duration, abort: &watch::Sender<bool>) {
+    let pending = in_flight.load(Ordering::SeqCst);
+    if pending == 0 {
+        return;
+    }
+
+    if !timeout.is_zero() {
+        eprintln!(
+            "Waiting up to {:.1}s for {} in-flight request(s)...",
+            timeout.as_secs_f64(),
+            pending
+        );
+    }
+    let settled = async {
+        while in_flight.load(Ordering::SeqCst) > 0 {
+            time::sleep(Duration::from_millis(10)).await;
+        }
+    };
+    if time::timeout(timeout, settled).await.is_err() {
+        let _ = abort.send(true);
+        while in_flight.load(Ordering::SeqCst) > 0 {
+            time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+}
+
+/// The once-a-second status line: elapsed and remaining time, or in a
+/// --requests run completed requests with a progress bar and ETA, followed on
+/// a terminal by a sparkline of recent requests per second.
+struct Progress {
+    start_time: Instant,
+    duration: Duration,
+    max_duration: Duration,
+    request_limit: Option<u32>,
+    /// Draw a progress bar for --requests and redraw the status line in
+    /// place with an RPS sparkline, only on a terminal
+    terminal: bool,
+    /// Set once the run has gone past its duration for --min-per-url
+    extending: bool,
+    show: bool,
+    rates: RateHistory,
+}
+
+impl Progress {
+    fn new(start_time: Instant, plan: &LoadPlan) -> Self {
+        Self {
+            start_time,
+            duration: plan.duration,
+            max_duration: plan.max_duration(),
+            request_limit: plan.request_limit,
+            terminal: std::io::stdout().is_terminal() && !output::to_stderr(),
+            extending: false,
+            show: plan.show_progress,
+            rates: RateHistory::new(SPARKLINE_SECONDS),
+        }
+    }
+
+    /// Print the status line; returns `false` once the run's time is up. Past
+    /// the duration, the run goes on while some target is short of
+    /// --min-per-url, up to the maximum duration.
+    async fn tick(&mut self, dispatcher: &Dispatcher) -> bool {
+        let metrics = &dispatcher.metrics;
+        let elapsed = self.start_time.elapsed();
+        let successful = *metrics.successful_requests.lock().await;
+        let completed = successful + *metrics.failed_requests.lock().await;
+        if let Some(limit) = self.request_limit {
+            if completed >= limit {
+                return false;
+            }
+        } else if elapsed >= self.duration {
+            if elapsed >= self.max_duration || !metrics.urls_short().await {
+                return false;
+            }
+            if !self.extending {
+                self.extending = true;
+                logln!(
+                    "{}Extending the run by up to {}s until every URL has --min-per-url requests{}",
+                    "".yellow(),
+                    (self.max_duration - self.duration).as_secs(),
+                    "".clear()
+                );
+            }
+        }
+        if !self.show {
+            return true;
+        }
+
+        let end = if self.extending {
+            self.max_duration
+        } else {
+            self.duration
+        };
+        let mut line = match self.request_limit {
+            Some(limit) => request_progress(completed, limit, elapsed, self.terminal),
+            None => format!(
+                "Time elapsed: {}s - Time remaining: {}s",
+                elapsed.as_secs(),
+                end.saturating_sub(elapsed).as_secs()
+            ),
+        };
+        line.push_str(&run_counts(
+            *dispatcher.total_requests.lock().await,
+            dispatcher.in_flight.load(Ordering::SeqCst),
+            successful,
+            completed,
+        ));
+        self.rates.sample(Instant::now(), completed);
+        if let Some(latest) = self.rates.latest() {
+            let sparkline = if self.terminal {
+                format!("{} ", self.rates.sparkline())
+            } else {
+                String::new()
+            };
+            line.push_str(&format!(" - RPS {}{:.1}", sparkline, latest));
+        }
+        if self.terminal {
+            output::show_status(&line);
+        } else {
+            logln!("\n{}", line);
+        }
+        true
+    }
+}
+
+/// Requests sent and in flight and the success rate so far for the status
+/// line, e.g. ` - Sent: 840 (12 in flight) - Success: 99.6%`.
+fn run_counts(sent: u32, in_flight: u32, successful: u32, completed: u32) -> String {
+    let success = match completed {
+        0 => "-".to_string(),
+        _ => format!("{:.1}%", successful as f64 / completed as f64 * 100.0),
+    };
+    format!(
+        " - Sent: {} ({} in flight) - Success: {}",
+        sent, in_flight, success
+    )
+}
+
+/// Progress of a --requests run: a bar with the ETA at the current pace, or
+/// without `bar` just the counts, e.g.
+/// `[#########.....................] 300/1000 (30%) - ETA 7s`.
+fn request_progress(completed: u32, limit: u32, elapsed: Duration, bar: bool) -> String {
+    let share = completed.min(limit) as f64 / limit as f64;
+    let counts = format!("{}/{} ({:.0}%)", completed, limit, share * 100.0);
+    if !bar {
+        return format!("Requests completed: {}", counts);
+    }
+
+    let filled = (share * PROGRESS_BAR_WIDTH as f64) as usize;
+    let eta = match completed {
+        0 => "-".to_string(),
+        _ => {
+            let remaining = elapsed.as_secs_f64() * (limit - completed.min(limit)) as f64;
+            format!("{:.0}s", remaining / completed as f64)
+        }
+    };
+    format!(
+        "[{}{}] {} - ETA {}",
+        "#".repeat(filled),
+        ".".repeat(PROGRESS_BAR_WIDTH - filled),
+        counts,
+        eta
+    )
+}
+
+/// Launch `concurrency` requests every second and wait for the whole batch.
+/// On a ramp the batch grows or shrinks towards `ramp_to`, and with
+/// backpressure tracked it follows the previous batch's 429 and 503
+/// responses instead.
+async fn run_ticks(dispatcher: &Dispatcher, plan: &Arc<LoadPlan>, start_time: Instant) {
+    let mut ticker = interval(Duration::from_secs(1));
+    let mut progress = Progress::new(start_time, plan);
+
+    while progress.tick(dispatcher).await {
+        let batch = match &dispatcher.metrics.backpressure {
+            Some(backpressure) => backpressure.lock().await.current(),
+            None => plan.batch_size(start_time.elapsed()),
+        };
+
+        // Launch concurrent requests for this interval
+        let mut handles = Vec::new();
+
+        for spawned in 0..batch {
+            if spawned > 0 && spawned % plan.spawn_batch == 0 {
+                yield_spawning(&dispatcher.metrics).await;
+            }
+            if !dispatcher.claim(plan) {
+                break;
+            }
+            let dispatcher = dispatcher.clone();
+            let plan = plan.clone();
+            handles.push(tokio::spawn(async move {
+                dispatcher.dispatch(&plan).await;
+            }));
+        }
+
+        // Wait for all requests in this interval to complete
+        for handle in handles {
+            let _ = handle.await;
+        }
+        if let Some(backpressure) = &dispatcher.metrics.backpressure {
+            backpressure.lock().await.adjust(start_time.elapsed());
+        }
+        if dispatcher.exhausted(plan) {
+            break;
+        }
+
+        ticker.tick().await;
+    }
+}
+
+/// Let the tasks spawned so far run before spawning the next batch, timing
+/// how long the scheduler kept the dispatch loop waiting.
+async fn yield_spawning(metrics: &RequestMetrics) {
+    let started = Instant::now();
+    tokio::task::yield_now().await;
+    metrics.record_spawn_yield(started.elapsed()).await;
+}
+
+/// Run `concurrency` long-lived workers for the whole duration. Each worker
+/// starts a request every `gap`, measured start to start: a request that takes
+/// longer than the gap is followed immediately by the next one. With a think
+/// time, the worker also pauses that long after each request completes. A
+/// pause is cut short at the deadline, so it never stretches the run.
+async fn run_persistent_workers(
+    dispatcher: &Dispatcher,
+    plan: &Arc<LoadPlan>,
+    gap: Duration,
+    start_time: Instant,
+    shutdown: &watch::Receiver<bool>,
+) {
+    let deadline = time::Instant::from_std(start_time + plan.max_duration());
+    // Whether a --min-per-url run goes past its duration is only known at the
+    // time, so the status loop tells the workers when to stop
+    let (done_sender, done) = watch::channel(false);
+
+    let mut workers = Vec::with_capacity(plan.concurrency as usize);
+    for spawned in 0..plan.concurrency {
+        if spawned > 0 && spawned % plan.spawn_batch == 0 {
+            yield_spawning(&dispatcher.metrics).await;
+        }
+        let dispatcher = dispatcher.clone();
+        let plan = plan.clone();
+        let shutdown = shutdown.clone();
+        let mut done = done.clone();
+        let start = time::Instant::from_std(start_time + plan.worker_start(spawned));
+        workers.push(tokio::spawn(async move {
+            let mut next_start = start.max(time::Instant::now());
+            let mut due = next_start;
+            if next_start > time::Instant::now() {
+                tokio::select! {
+                    _ = time::sleep_until(next_start.min(deadline)) => {},
+                    _ = done.wait_for(|done| *done) => {},
+                }
+            }
+            while next_start < deadline
+                && !is_shutdown(&shutdown)
+                && !*done.borrow()
+                && dispatcher.claim(&plan)
+            {
+                dispatcher
+                    .metrics
+                    .record_dispatch(time::Instant::now().saturating_duration_since(due))
+                    .await;
+                dispatcher.dispatch(&plan).await;
+
+                // A late request pushes the schedule back rather than
+                // causing a burst to catch up
+                due = next_start + gap;
+                next_start = due.max(time::Instant::now());
+                if let Some(think) = &plan.think_time {
+                    // Thinking is deliberate, so it doesn't count as lateness
+                    next_start = next_start.max(time::Instant::now() + think.pick());
+                    due = next_start;
+                }
+                tokio::select! {
+                    _ = time::sleep_until(next_start.min(deadline)) => {},
+                    _ = done.wait_for(|done| *done) => {},
+                }
+            }
+        }));
+    }
+
+    let finished = async {
+        for worker in workers {
+            let _ = worker.await;
+        }
+    };
+    tokio::pin!(finished);
+
+    let mut ticker = interval(Duration::from_secs(1));
+    let mut progress = Progress::new(start_time, plan);
+    let status = async {
+        while progress.tick(dispatcher).await {
+            ticker.tick().await;
+        }
+    };
+    // Workers of a --requests run stop on their own after the last request
+    tokio::select! {
+        _ = &mut finished => return,
+        _ = status => {}
+    }
+    let _ = done_sender.send(true);
+    finished.await;
+}
+
+/// Start `rps` requests per second for the whole duration without waiting
+/// for earlier ones, so slow responses pile up in flight instead of slowing
+/// the arrivals. Requests are due at fixed offsets from the start; all that
+/// are due start together, so rates finer than the timer still come out
+/// right and a stalled dispatcher catches up rather than falling behind.
+async fn run_open_loop(
+    dispatcher: &Dispatcher,
+    plan: &Arc<LoadPlan>,
+    rps: f64,
+    start_time: Instant,
+    shutdown: &watch::Receiver<bool>,
+) {
+    let started = time::Instant::from_std(start_time);
+    let deadline = time::Instant::from_std(start_time + plan.max_duration());
+    let due_at = |sequence: u64| started + Duration::from_secs_f64(sequence as f64 / rps);
+    let (done_sender, done) = watch::channel(false);
+
+    let arrivals = async {
+        let mut done = done.clone();
+        let mut handles = Vec::new();
+        let mut started_requests: u64 = 0;
+        'arrivals: loop {
+            let now = time::Instant::now();
+            while due_at(started_requests) <= now {
+                let due = due_at(started_requests);
+                if due >= deadline || is_shutdown(shutdown) || !dispatcher.claim(plan) {
+                    break 'arrivals;
+                }
+                dispatcher
+                    .metrics
+                    .record_dispatch(now.saturating_duration_since(due))
+                    .await;
+                if started_requests > 0 && started_requests.is_multiple_of(plan.spawn_batch as u64)
+                {
+                    yield_spawning(&dispatcher.metrics).await;
+                }
+                let dispatcher = dispatcher.clone();
+                let plan = plan.clone();
+                handles.push(tokio::spawn(async move {
+                    dispatcher.dispatch(&plan).await;
+                }));
+                started_requests += 1;
+            }
+            // Finished requests needn't be waited for at the end
+            handles.retain(|handle| !handle.is_finished());
+
+            let next = due_at(started_requests);
+            if next >= deadline || *done.borrow() {
+                break;
+            }
+            tokio::select! {
+                _ = time::sleep_until(next) => {},
+                _ = done.wait_for(|done| *done) => break,
+            }
+        }
+        for handle in handles {
+            let _ = handle.await;
+        }
+    };
+    tokio::pin!(arrivals);
+
+    let mut ticker = interval(Duration::from_secs(1));
+    let mut progress = Progress::new(start_time, plan);
+    let status = async {
+        while progress.tick(dispatcher).await {
+            ticker.tick().await;
+        }
+    };
+    // A --requests run stops on its own after the last request
+    tokio::select! {
+        _ = &mut arrivals => return,
+        _ = status => {}
+    }
+    let _ = done_sender.send(true);
+    arrivals.await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::Capture;
+    use crate::requests::{DEFAULT_RETRY_BASE_DELAY, DEFAULT_RETRY_MAX_DELAY};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn test_plan(url: &str) -> LoadPlan {
+        LoadPlan {
+            targets: Arc::new(vec![Target::new(url, Duration::from_secs(1))]),
+            chain: false,
+            target_weights: None,
+            methods: vec![Method::GET],
+            body: None,
+            concurrency: 1,
+            ramp_to: None,
+            ramp_up: Duration::ZERO,
+            spawn_batch: DEFAULT_SPAWN_BATCH,
+            duration: Duration::from_secs(60),
+            request_limit: None,
+            retries: 0,
+            raw_latencies: false,
+            max_samples: None,
+            gap: None,
+            think_time: None,
+            rps: None,
+            randomize_headers: false,
+            read_body: None,
+            show_progress: true,
+            timeseries: false,
+            summary_interval: None,
+            auth: None,
+            auth_refresh_interval: None,
+            correlation: None,
+            expect_headers: Arc::new(Vec::new()),
+            response_schema: None,
+            body_size: None,
+            timing_breakdown: false,
+            compress_request: false,
+            fail_on: FailOn::default(),
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+            timeout_growth: 1.0,
+            retry_base_delay: DEFAULT_RETRY_BASE_DELAY,
+            retry_max_delay: DEFAULT_RETRY_MAX_DELAY,
+            apdex_threshold: None,
+            hard_deadline: None,
+            idempotency_check: false,
+            bandwidth: None,
+            max_bytes: None,
+            backpressure_floor: None,
+            folded: false,
+            min_per_url: None,
+            dns_timings: None,
+            records: None,
+            server_timing: false,
+            exclusive_clients: false,
+            #[cfg(feature = "ntlm")]
+            ntlm: None,
+        }
+    }
+
+    #[test]
+    fn test_shuffle_headers_reorders_without_losing_any() {
+        let headers: Vec<(String, String)> = ["Accept", "X-Trace", "X-Tenant", "User-Agent"]
+            .iter()
+            .map(|name| (name.to_string(), "1".to_string()))
+            .collect();
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut orders = Vec::new();
+        for _ in 0..20 {
+            let mut shuffled = headers.clone();
+            shuffle_headers(&mut shuffled, &mut rng);
+            let mut sorted = shuffled.clone();
+            sorted.sort();
+            let mut expected = headers.clone();
+            expected.sort();
+            assert_eq!(sorted, expected);
+            if !orders.contains(&shuffled) {
+                orders.push(shuffled);
+            }
+        }
+        assert!(orders.len() > 1, "the order never changed");
+    }
+
+    #[tokio::test]
+    async fn test_run_load_stops_on_shutdown() {
+        let (sender, receiver) = watch::channel(false);
+        sender.send(true).unwrap();
+
+        let plan = Arc::new(test_plan("http://127.0.0.1:9"));
+
+        let outcome = run_load(vec![reqwest::Client::new()], plan, &receiver).await;
+        assert!(outcome.elapsed < Duration::from_secs(60));
+    }
+
+    #[tokio::test]
+    async fn test_abort_file_stops_the_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("abort");
+        let (sender, mut receiver) = watch::channel(false);
+        tokio::spawn(watch_abort_file(path.clone(), Arc::new(sender)));
+
+        time::sleep(ABORT_FILE_POLL_INTERVAL * 2).await;
+        assert!(!is_shutdown(&receiver));
+
+        std::fs::write(&path, "").unwrap();
+        time::timeout(Duration::from_secs(5), receiver.wait_for(|stop| *stop))
+            .await
+            .expect("the abort file should stop the run")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_drain_timeout_aborts_stuck_requests() {
+        // Accept connections but never answer them
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let mut sockets = Vec::new();
+            while let Ok((socket, _)) = listener.accept().await {
+                sockets.push(socket);
+            }
+        });
+        let (sender, receiver) = watch::channel(false);
+        let mut plan = test_plan(&url);
+        plan.concurrency = 2;
+        plan.targets = Arc::new(vec![Target::new(&url, Duration::from_secs(30))]);
+        plan.drain_timeout = Duration::from_millis(100);
+        tokio::spawn(async move {
+            time::sleep(Duration::from_millis(300)).await;
+            sender.send(true).unwrap();
+            sender.closed().await;
+        });
+
+        let outcome = run_load(vec![reqwest::Client::new()], Arc::new(plan), &receiver).await;
+
+        assert!(outcome.elapsed < Duration::from_secs(5));
+        assert_eq!(outcome.total_requests, 2);
+        assert_eq!(*outcome.metrics.aborted_requests.lock().await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_run_load_spawns_in_batches() {
+        let mut server = mockito::Server::new_async().await;
+        server.mock("GET", "/").expect(10).create_async().await;
+        let url = format!("{}/", server.url());
+        let (_sender, receiver) = watch::channel(false);
+        let mut plan = test_plan(&url);
+        plan.concurrency = 10;
+        plan.spawn_batch = 3;
+        plan.request_limit = Some(10);
+        plan.targets = Arc::new(vec![Target::new(&url, Duration::from_secs(5))]);
+
+        let outcome = run_load(vec![reqwest::Client::new()], Arc::new(plan), &receiver).await;
+
+        assert_eq!(outcome.total_requests, 10);
+        let stalls = *outcome.metrics.spawn_stalls.as_ref().unwrap().lock().await;
+        assert_eq!(stalls.batch_size, 3);
+        // Batches of 3, 3, 3 and 1, with a yield between each
+        assert_eq!(stalls.yields, 3);
+        let in_flight = *outcome.metrics.in_flight.as_ref().unwrap().lock().await;
+        assert!(in_flight.samples > 0);
+        assert!(in_flight.max <= 10);
+    }
+
+    #[tokio::test]
+    async fn test_run_load_stops_at_byte_cap() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/")
+            .with_body("x".repeat(1000))
+            .expect_at_least(3)
+            .create_async()
+            .await;
+        let url = format!("{}/", server.url());
+        let (_sender, receiver) = watch::channel(false);
+        let mut plan = test_plan(&url);
+        plan.concurrency = 2;
+        plan.duration = Duration::from_secs(30);
+        plan.targets = Arc::new(vec![Target::new(&url, Duration::from_secs(5))]);
+        plan.read_body = Some(DEFAULT_MAX_BODY_BYTES);
+        plan.max_bytes = Some(2500);
+
+        let outcome = run_load(vec![reqwest::Client::new()], Arc::new(plan), &receiver).await;
+
+        assert!(outcome.elapsed < Duration::from_secs(5));
+        assert!(outcome.metrics.byte_cap_exceeded());
+    }
+
+    #[tokio::test]
+    async fn test_min_per_url_extends_the_run() {
+        let mut server = mockito::Server::new_async().await;
+        server.mock("GET", "/a").create_async().await;
+        server.mock("GET", "/b").create_async().await;
+        let (_sender, receiver) = watch::channel(false);
+        let mut plan = test_plan(&server.url());
+        plan.concurrency = 2;
+        plan.duration = Duration::from_secs(1);
+        plan.targets = Arc::new(vec![
+            Target::new(&format!("{}/a", server.url()), Duration::from_secs(5)),
+            Target::new(&format!("{}/b", server.url()), Duration::from_secs(5)),
+        ]);
+        plan.min_per_url = Some(2);
+
+        let outcome = run_load(vec![reqwest::Client::new()], Arc::new(plan), &receiver).await;
+
+        assert!(outcome.elapsed >= Duration::from_secs(1));
+        let samples = outcome.metrics.url_samples.unwrap();
+        let samples = samples.lock().await;
+        assert!(samples.short().is_empty());
+        assert_eq!(samples.counts[0].1, 2);
+    }
+
+    #[tokio::test]
+    async fn test_weighted_targets_record_per_url_stats() {
+        let mut server = mockito::Server::new_async().await;
+        server.mock("GET", "/a").create_async().await;
+        server.mock("GET", "/b").create_async().await;
+        let (_sender, receiver) = watch::channel(false);
+        let mut plan = test_plan(&server.url());
+        plan.concurrency = 20;
+        plan.request_limit = Some(20);
+        plan.targets = Arc::new(vec![
+            Target::new(&format!("{}/a", server.url()), Duration::from_secs(5)),
+            Target::new(&format!("{}/b", server.url()), Duration::from_secs(5)),
+        ]);
+        plan.target_weights = Some(TargetWeights::new(vec![1.0, 0.0]).unwrap());
+
+        let outcome = run_load(vec![reqwest::Client::new()], Arc::new(plan), &receiver).await;
+
+        let stats = outcome.metrics.url_stats.unwrap();
+        let stats = stats.lock().await;
+        assert_eq!(stats[0].latency.count(), 20);
+        assert_eq!(stats[1].latency.count(), 0);
+        assert_eq!(stats[1].weight, 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_per_url_latency_leaves_out_failed_attempts() {
+        let mut server = mockito::Server::new_async().await;
+        let failure = server
+            .mock("GET", "/")
+            .with_status(503)
+            .expect(1)
+            .create_async()
+            .await;
+        server.mock("GET", "/").create_async().await;
+        let (_sender, receiver) = watch::channel(false);
+        let mut plan = test_plan(&server.url());
+        plan.request_limit = Some(1);
+        plan.retries = 1;
+        plan.retry_base_delay = Duration::from_millis(500);
+        plan.retry_max_delay = Duration::from_millis(500);
+        plan.target_weights = Some(TargetWeights::new(vec![1.0]).unwrap());
+
+        let outcome = run_load(vec![reqwest::Client::new()], Arc::new(plan), &receiver).await;
+
+        failure.assert_async().await;
+        let stats = outcome.metrics.url_stats.unwrap();
+        let stats = stats.lock().await;
+        assert_eq!(stats[0].latency.count(), 1);
+        // The backoff before the second attempt isn't part of its latency
+        assert!(stats[0].latency.histogram().max() < 200_000);
+    }
+
+    #[tokio::test]
+    async fn test_chain_records_journey_times() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/login")
+            .with_body(r#"{"token": "abc"}"#)
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/me")
+            .match_header("authorization", "Bearer abc")
+            .create_async()
+            .await;
+        let mut login = Target::new(&format!("{}/login", server.url()), Duration::from_secs(1));
+        login.method = Some(Method::POST);
+        login.captures = vec![Capture::parse("token", "$.token").unwrap()];
+        let mut me = Target::new(&format!("{}/me", server.url()), Duration::from_secs(1));
+        me.headers = vec![(
+            "Authorization".to_string(),
+            "Bearer {captured.token}".to_string(),
+        )];
+        let mut plan = test_plan(&server.url());
+        plan.targets = Arc::new(vec![login, me]);
+        plan.chain = true;
+        plan.duration = Duration::from_millis(500);
+        plan.show_progress = false;
+        let (_sender, receiver) = watch::channel(false);
+
+        let outcome = run_load(vec![reqwest::Client::new()], Arc::new(plan), &receiver).await;
+
+        let journeys = outcome.metrics.journeys.as_ref().unwrap().lock().await;
+        let labels: Vec<&str> = journeys
+            .steps
+            .iter()
+            .map(|(label, _)| label.as_str())
+            .collect();
+        assert_eq!(labels, vec!["1. POST /login", "2. GET /me"]);
+        assert!(journeys.completed.count() > 0);
+        assert_eq!(journeys.steps[1].1.count(), journeys.completed.count());
+        assert_eq!(journeys.abandoned, 0);
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_summarizes_results_so_far() {
+        let metrics = RequestMetrics::new(false, None, false);
+        for ms in [10, 20, 30, 40] {
+            metrics
+                .request_times
+                .lock()
+                .await
+                .record(Duration::from_millis(ms));
+        }
+        *metrics.successful_requests.lock().await = 3;
+        *metrics.failed_requests.lock().await = 1;
+
+        assert_eq!(
+            snapshot(&metrics, 4, 1, Duration::from_secs(2)).await,
+            "Summary at 2s: rps=2 p50=20ms p95=n/a p99=n/a err=25.0% n=4"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_think_time_paces_persistent_workers() {
+        let mut server = mockito::Server::new_async().await;
+        server.mock("GET", "/").create_async().await;
+        let (_stop, shutdown) = watch::channel(false);
+        let mut plan = test_plan(&format!("{}/", server.url()));
+        plan.concurrency = 2;
+        plan.duration = Duration::from_secs(1);
+        plan.think_time = Some(ThinkTime::parse("400").unwrap());
+        assert_eq!(plan.worker_pacing(), Some(Duration::from_millis(400)));
+
+        let outcome = run_load(vec![reqwest::Client::new()], Arc::new(plan), &shutdown).await;
+
+        // Each worker requests at about 0ms, 400ms and 800ms; the pause
+        // after the last ends at the deadline instead of running over it
+        assert_eq!(outcome.total_requests, 6);
+        assert!(outcome.elapsed < Duration::from_millis(1300));
+    }
+
+    #[tokio::test]
+    async fn test_request_limit_ends_the_run() {
+        let mut plan = test_plan("http://127.0.0.1:9");
+        plan.concurrency = 4;
+        plan.request_limit = Some(6);
+        let (_stop, shutdown) = watch::channel(false);
+
+        let outcome = run_load(
+            vec![reqwest::Client::new()],
+            Arc::new(plan.clone()),
+            &shutdown,
+        )
+        .await;
+        assert_eq!(outcome.total_requests, 6);
+        assert!(outcome.elapsed < Duration::from_secs(5));
+
+        plan.gap = Some(Duration::from_millis(10));
+        let outcome = run_load(vec![reqwest::Client::new()], Arc::new(plan), &shutdown).await;
+        assert_eq!(outcome.total_requests, 6);
+        assert!(outcome.elapsed < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_rps_arrivals_ignore_latency() {
+        // A server that accepts connections but never answers, so every
+        // request hangs until its one-second timeout
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                held.push(stream);
+            }
+        });
+        let mut plan = test_plan(&format!("http://{}", address));
+        plan.duration = Duration::from_secs(1);
+        plan.rps = Some(40.0);
+        let (_stop, shutdown) = watch::channel(false);
+
+        let outcome = run_load(vec![reqwest::Client::new()], Arc::new(plan), &shutdown).await;
+        server.abort();
+
+        // A closed loop would have started one request and waited for it
+        assert!(
+            (35..=40).contains(&outcome.total_requests),
+            "{}",
+            outcome.total_requests
+        );
+        let accuracy = outcome.metrics.rate_accuracy.as_ref().unwrap();
+        assert_eq!(accuracy.lock().await.requested, 40.0);
+        assert_eq!(
+            *outcome.metrics.failed_requests.lock().await,
+            outcome.total_requests
+        );
+    }
+
+    #[test]
+    fn test_run_counts() {
+        assert_eq!(
+            run_counts(840, 12, 824, 828),
+            " - Sent: 840 (12 in flight) - Success: 99.5%"
+        );
+        assert_eq!(
+            run_counts(5, 5, 0, 0),
+            " - Sent: 5 (5 in flight) - Success: -"
+        );
+    }
+
+    #[test]
+    fn test_request_progress() {
+        assert_eq!(
+            request_progress(300, 1000, Duration::from_secs(3), true),
+            "[#########.....................] 300/1000 (30%) - ETA 7s"
+        );
+        assert_eq!(
+            request_progress(0, 1000, Duration::from_secs(1), true),
+            "[..............................] 0/1000 (0%) - ETA -"
+        );
+        assert_eq!(
+            request_progress(300, 1000, Duration::from_secs(3), false),
+            "Requests completed: 300/1000 (30%)"
+        );
+    }
+
+    #[test]
+    fn test_batch_size_ramps_over_the_duration() {
+        let mut plan = test_plan("http://127.0.0.1:9");
+        plan.concurrency = 50;
+        plan.duration = Duration::from_secs(30);
+        assert_eq!(plan.batch_size(Duration::from_secs(15)), 50);
+
+        plan.ramp_to = Some(0);
+        assert_eq!(plan.batch_size(Duration::ZERO), 50);
+        assert_eq!(plan.batch_size(Duration::from_secs(15)), 25);
+        assert_eq!(plan.batch_size(Duration::from_secs(29)), 2);
+        assert_eq!(plan.batch_size(Duration::from_secs(40)), 0);
+
+        plan.concurrency = 10;
+        plan.ramp_to = Some(20);
+        assert_eq!(plan.batch_size(Duration::from_secs(3)), 11);
+    }
+
+    #[test]
+    fn test_batch_size_ramps_up_from_one() {
+        let mut plan = test_plan("http://127.0.0.1:9");
+        plan.concurrency = 21;
+        plan.duration = Duration::from_secs(30);
+        plan.ramp_up = Duration::from_secs(10);
+
+        assert_eq!(plan.batch_size(Duration::ZERO), 1);
+        assert_eq!(plan.batch_size(Duration::from_secs(5)), 11);
+        assert_eq!(plan.batch_size(Duration::from_secs(9)), 19);
+        assert_eq!(plan.batch_size(Duration::from_secs(10)), 21);
+        assert_eq!(plan.batch_size(Duration::from_secs(20)), 21);
+
+        assert_eq!(plan.worker_start(0), Duration::ZERO);
+        assert_eq!(plan.worker_start(7), Duration::from_secs_f64(10.0 / 3.0));
+    }
+
+    #[test]
+    fn test_signal_counter_forces_quit_within_window() {
+        let start = Instant::now();
+        let mut counter = SignalCounter::new(2);
+
+        assert_eq!(counter.press(start), SignalAction::Drain);
+        assert_eq!(
+            counter.press(start + Duration::from_secs(1)),
+            SignalAction::ForceQuit
+        );
+    }
+
+    #[test]
+    fn test_signal_counter_window_expires() {
+        let start = Instant::now();
+        let mut counter = SignalCounter::new(2);
+
+        assert_eq!(counter.press(start), SignalAction::Drain);
+        let later = start + FORCE_QUIT_WINDOW + Duration::from_secs(1);
+        assert_eq!(counter.press(later), SignalAction::Drain);
+        assert_eq!(counter.press(later), SignalAction::ForceQuit);
+
+        assert_eq!(SignalCounter::new(1).press(start), SignalAction::ForceQuit);
+    }
+}
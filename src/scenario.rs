@@ -0,0 +1,397 @@
+use rand::distr::weighted::WeightedIndex;
+use rand::distr::Distribution;
+use reqwest::Method;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::time::Duration;
+use url::Url;
+
+use crate::chain::Capture;
+
+/// A set of endpoints to load in a single run, read from a TOML file:
+///
+/// ```toml
+/// [[request]]
+/// url = "https://example.com/fast"
+///
+/// [[request]]
+/// url = "https://example.com/slow"
+/// timeout = 30
+/// ```
+///
+/// With `chain = true`, each virtual user runs the requests in order instead,
+/// and values captured from one response can be used by later steps.
+#[derive(Debug, Deserialize)]
+pub struct Scenario {
+    #[serde(default)]
+    pub chain: bool,
+    #[serde(rename = "request", default)]
+    pub requests: Vec<RequestSpec>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RequestSpec {
+    pub url: String,
+    /// Per-request timeout in seconds, overriding the global `--timeout`
+    pub timeout: Option<u64>,
+    pub method: Option<String>,
+    pub body: Option<String>,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// Values to capture from the response, keyed by name
+    #[serde(default)]
+    pub capture: BTreeMap<String, String>,
+}
+
+/// A fully resolved endpoint that worker tasks send requests to.
+#[derive(Debug, Clone)]
+pub struct Target {
+    pub url: String,
+    pub timeout: Duration,
+    /// Overrides the global method mix when set
+    pub method: Option<Method>,
+    /// Overrides the global request body when set
+    pub body: Option<String>,
+    pub headers: Vec<(String, String)>,
+    pub captures: Vec<Capture>,
+    /// In a chain, don't send this step before this long into the journey,
+    /// to keep the pacing of a recorded session
+    pub start_offset: Option<Duration>,
+}
+
+impl Target {
+    /// A plain target with no per-request overrides.
+    pub fn new(url: &str, timeout: Duration) -> Self {
+        Self {
+            url: url.to_string(),
+            timeout,
+            method: None,
+            body: None,
+            headers: Vec::new(),
+            captures: Vec::new(),
+            start_offset: None,
+        }
+    }
+}
+
+/// Read a targets file: one URL per line, skipping blank lines and `#` comments.
+pub fn load_targets_file(path: &str) -> Result<Vec<String>, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    parse_targets(&content).map_err(|e| format!("{}: {}", path, e))
+}
+
+fn parse_targets(content: &str) -> Result<Vec<String>, String> {
+    let urls: Vec<String> = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect();
+
+    if urls.is_empty() {
+        return Err("no targets listed".to_string());
+    }
+    if let Some(url) = urls.iter().find(|url| Url::parse(url).is_err()) {
+        return Err(format!("invalid target URL '{}'", url));
+    }
+    Ok(urls)
+}
+
+/// A `--url` value: the URL and, after a `|`, its share of the requests.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WeightedUrl {
+    pub url: String,
+    pub weight: f64,
+}
+
+impl WeightedUrl {
+    /// Parse `URL` or `URL|WEIGHT`; the weight defaults to 1. Only a number
+    /// after the last `|` is a weight, so a URL such as `?q=a|b` stays whole.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (url, weight) = match spec.rsplit_once('|') {
+            Some((url, weight)) => match weight.trim().parse::<f64>() {
+                Ok(weight) if weight.is_finite() && weight >= 0.0 => (url.trim(), weight),
+                Ok(_) => {
+                    return Err(format!(
+                        "Invalid weight in '{}': must be a number >= 0",
+                        spec
+                    ))
+                }
+                Err(_) => (spec.trim(), 1.0),
+            },
+            None => (spec.trim(), 1.0),
+        };
+        if Url::parse(url).is_err() {
+            return Err(format!("Invalid target URL: {}", url));
+        }
+        Ok(Self {
+            url: url.to_string(),
+            weight,
+        })
+    }
+}
+
+/// Parse every `--url` value. Their weights must add up to more than zero.
+pub fn parse_weighted_urls(specs: &[String]) -> Result<Vec<WeightedUrl>, String> {
+    let urls = specs
+        .iter()
+        .map(|spec| WeightedUrl::parse(spec))
+        .collect::<Result<Vec<_>, _>>()?;
+    if !urls.is_empty() && urls.iter().map(|url| url.weight).sum::<f64>() <= 0.0 {
+        return Err("The --url weights must add up to more than 0".to_string());
+    }
+    Ok(urls)
+}
+
+/// Picks targets at random in proportion to their weights.
+#[derive(Debug, Clone)]
+pub struct TargetWeights {
+    weights: Vec<f64>,
+    index: WeightedIndex<f64>,
+}
+
+impl TargetWeights {
+    pub fn new(weights: Vec<f64>) -> Result<Self, String> {
+        let index = WeightedIndex::new(&weights).map_err(|e| format!("Invalid weights: {}", e))?;
+        Ok(Self { weights, index })
+    }
+
+    pub fn weights(&self) -> &[f64] {
+        &self.weights
+    }
+
+    /// The index of the next target to request.
+    pub fn pick(&self) -> usize {
+        self.index.sample(&mut rand::rng())
+    }
+}
+
+impl Scenario {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let content = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let scenario: Scenario =
+            toml::from_str(&content).map_err(|e| format!("{}: {}", path, e))?;
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.requests.is_empty() {
+            return Err("Scenario must define at least one [[request]]".to_string());
+        }
+
+        for spec in &self.requests {
+            // Captured placeholders are only known at run time
+            if !spec.url.contains("{captured.") && Url::parse(&spec.url).is_err() {
+                return Err(format!("Invalid URL in scenario: {}", spec.url));
+            }
+            if spec.timeout == Some(0) {
+                return Err(format!("Timeout must be > 0 for {}", spec.url));
+            }
+            if let Some(method) = &spec.method {
+                parse_method(method)?;
+            }
+            if !spec.capture.is_empty() && !self.chain {
+                return Err(format!(
+                    "Captures for {} require chain = true in the scenario",
+                    spec.url
+                ));
+            }
+            for (name, source) in &spec.capture {
+                Capture::parse(name, source)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Resolve every entry into a target, falling back to `default_timeout`.
+    /// Assumes the scenario has been validated.
+    pub fn targets(&self, default_timeout: Duration) -> Vec<Target> {
+        self.requests
+            .iter()
+            .map(|spec| Target {
+                url: spec.url.clone(),
+                timeout: spec
+                    .timeout
+                    .map(Duration::from_secs)
+                    .unwrap_or(default_timeout),
+                method: spec.method.as_deref().and_then(|m| parse_method(m).ok()),
+                body: spec.body.clone(),
+                headers: spec
+                    .headers
+                    .iter()
+                    .map(|(name, value)| (name.clone(), value.clone()))
+                    .collect(),
+                captures: spec
+                    .capture
+                    .iter()
+                    .filter_map(|(name, source)| Capture::parse(name, source).ok())
+                    .collect(),
+                start_offset: None,
+            })
+            .collect()
+    }
+}
+
+fn parse_method(method: &str) -> Result<Method, String> {
+    Method::from_bytes(method.trim().to_uppercase().as_bytes())
+        .map_err(|_| format!("Invalid HTTP method in scenario: {}", method))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_resolve_timeouts() {
+        let scenario: Scenario = toml::from_str(
+            r#"
+            [[request]]
+            url = "https://example.com/fast"
+
+            [[request]]
+            url = "https://example.com/slow"
+            timeout = 30
+            "#,
+        )
+        .unwrap();
+        assert!(scenario.validate().is_ok());
+
+        let targets = scenario.targets(Duration::from_secs(5));
+        assert_eq!(targets.len(), 2);
+        assert_eq!(targets[0].timeout, Duration::from_secs(5));
+        assert_eq!(targets[1].url, "https://example.com/slow");
+        assert_eq!(targets[1].timeout, Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_validate_rejects_empty() {
+        let scenario: Scenario = toml::from_str("").unwrap();
+        assert!(scenario.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_rejects_bad_entries() {
+        let scenario: Scenario = toml::from_str("[[request]]\nurl = \"nope\"").unwrap();
+        assert_eq!(
+            scenario.validate().unwrap_err(),
+            "Invalid URL in scenario: nope"
+        );
+
+        let scenario: Scenario =
+            toml::from_str("[[request]]\nurl = \"https://example.com\"\ntimeout = 0").unwrap();
+        assert!(scenario.validate().is_err());
+    }
+
+    #[test]
+    fn test_parse_chain() {
+        let scenario: Scenario = toml::from_str(
+            r#"
+            chain = true
+
+            [[request]]
+            url = "https://example.com/login"
+            method = "post"
+            body = '{"user":"demo"}'
+            capture = { token = "$.token" }
+
+            [[request]]
+            url = "https://example.com/me"
+            headers = { Authorization = "Bearer {captured.token}" }
+            "#,
+        )
+        .unwrap();
+        assert!(scenario.validate().is_ok());
+
+        let targets = scenario.targets(Duration::from_secs(5));
+        assert_eq!(targets[0].method, Some(Method::POST));
+        assert_eq!(targets[0].captures.len(), 1);
+        assert_eq!(
+            targets[1].headers,
+            vec![(
+                "Authorization".to_string(),
+                "Bearer {captured.token}".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_validate_rejects_captures_without_chain() {
+        let scenario: Scenario = toml::from_str(
+            "[[request]]\nurl = \"https://example.com\"\ncapture = { token = \"$.token\" }",
+        )
+        .unwrap();
+        assert!(scenario.validate().is_err());
+    }
+
+    #[test]
+    fn test_load_missing_file() {
+        assert!(Scenario::load("/nonexistent/scenario.toml").is_err());
+    }
+
+    #[test]
+    fn test_parse_targets() {
+        let urls =
+            parse_targets("# backends\nhttps://a.example.com\n\n  https://b.example.com  \n")
+                .unwrap();
+        assert_eq!(urls, vec!["https://a.example.com", "https://b.example.com"]);
+
+        assert!(parse_targets("# nothing here\n").is_err());
+        assert!(parse_targets("https://a.example.com\nnot a url\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_weighted_urls() {
+        let urls = parse_weighted_urls(&[
+            "https://a.example.com/|3".to_string(),
+            "https://b.example.com/".to_string(),
+            "https://c.example.com/ | 0.5".to_string(),
+        ])
+        .unwrap();
+        let parsed: Vec<(&str, f64)> = urls.iter().map(|u| (u.url.as_str(), u.weight)).collect();
+        assert_eq!(
+            parsed,
+            [
+                ("https://a.example.com/", 3.0),
+                ("https://b.example.com/", 1.0),
+                ("https://c.example.com/", 0.5)
+            ]
+        );
+
+        for spec in [
+            "https://a/|-1",
+            "https://a/|inf",
+            "not a url|2",
+            "not a url",
+        ] {
+            assert!(WeightedUrl::parse(spec).is_err(), "{}", spec);
+        }
+        let zero = ["https://a/|0".to_string(), "https://b/|0".to_string()];
+        assert!(parse_weighted_urls(&zero).is_err());
+        assert!(parse_weighted_urls(&zero[..1]).is_err());
+    }
+
+    #[test]
+    fn test_weighted_url_keeps_a_pipe_that_is_no_weight() {
+        let url = WeightedUrl::parse("http://127.0.0.1:9/search?q=a|b").unwrap();
+        assert_eq!(url.url, "http://127.0.0.1:9/search?q=a|b");
+        assert_eq!(url.weight, 1.0);
+
+        let url = WeightedUrl::parse("http://127.0.0.1:9/search?q=a|b|2").unwrap();
+        assert_eq!(url.url, "http://127.0.0.1:9/search?q=a|b");
+        assert_eq!(url.weight, 2.0);
+    }
+
+    #[test]
+    fn test_target_weights_follow_the_weights() {
+        let weights = TargetWeights::new(vec![3.0, 0.0, 1.0]).unwrap();
+        let mut counts = [0u32; 3];
+        for _ in 0..4000 {
+            counts[weights.pick()] += 1;
+        }
+        assert_eq!(counts[1], 0);
+        assert!((2700..3300).contains(&counts[0]), "{:?}", counts);
+    }
+}
@@ -0,0 +1,414 @@
+use serde_json::{Map, Value};
+
+/// Keywords that only describe a schema and never fail validation.
+const ANNOTATIONS: [&str; 8] = [
+    "$schema",
+    "$id",
+    "$comment",
+    "title",
+    "description",
+    "default",
+    "examples",
+    "format",
+];
+
+/// Keywords the validator enforces.
+const SUPPORTED: [&str; 20] = [
+    "type",
+    "enum",
+    "const",
+    "properties",
+    "required",
+    "additionalProperties",
+    "items",
+    "minItems",
+    "maxItems",
+    "minimum",
+    "maximum",
+    "exclusiveMinimum",
+    "exclusiveMaximum",
+    "minLength",
+    "maxLength",
+    "allOf",
+    "anyOf",
+    "oneOf",
+    "not",
+    "uniqueItems",
+];
+
+const TYPES: [&str; 7] = [
+    "null", "boolean", "object", "array", "number", "integer", "string",
+];
+
+/// A JSON Schema that response bodies must match, checked once at startup.
+///
+/// Covers the structural subset of JSON Schema used for API contracts: types,
+/// `enum`/`const`, object properties, array items, numeric and length bounds,
+/// and the `allOf`/`anyOf`/`oneOf`/`not` combinators. Schemas using anything
+/// else, such as `$ref` or `pattern`, are rejected rather than half-checked.
+#[derive(Debug)]
+pub struct ResponseSchema {
+    root: Value,
+}
+
+impl ResponseSchema {
+    pub fn compile(schema: Value) -> Result<Self, String> {
+        check_schema(&schema, "#")?;
+        Ok(Self { root: schema })
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let content =
+            std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+        let schema: Value =
+            serde_json::from_str(&content).map_err(|e| format!("{}: invalid JSON: {}", path, e))?;
+        Self::compile(schema).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    /// Check a response body, returning the first violation found.
+    pub fn validate(&self, body: &[u8]) -> Result<(), String> {
+        let instance: Value =
+            serde_json::from_slice(body).map_err(|e| format!("body is not JSON: {}", e))?;
+        validate(&self.root, &instance, "$")
+    }
+}
+
+/// Reject schemas with keywords the validator doesn't enforce, or with
+/// keyword values of the wrong shape. `at` is a JSON pointer for messages.
+fn check_schema(schema: &Value, at: &str) -> Result<(), String> {
+    let object = match schema {
+        Value::Bool(_) => return Ok(()),
+        Value::Object(object) => object,
+        _ => return Err(format!("{}: a schema must be an object or a boolean", at)),
+    };
+
+    for (keyword, value) in object {
+        let at = format!("{}/{}", at, keyword);
+        if ANNOTATIONS.contains(&keyword.as_str()) {
+            continue;
+        }
+        if !SUPPORTED.contains(&keyword.as_str()) {
+            return Err(format!("{}: unsupported keyword '{}'", at, keyword));
+        }
+        match keyword.as_str() {
+            "type" => {
+                let names: Vec<&Value> = match value {
+                    Value::Array(names) => names.iter().collect(),
+                    name => vec![name],
+                };
+                if !names
+                    .iter()
+                    .all(|name| name.as_str().is_some_and(|name| TYPES.contains(&name)))
+                {
+                    return Err(format!("{}: unknown type {}", at, value));
+                }
+            }
+            "enum" if !value.is_array() => {
+                return Err(format!("{}: expected an array", at));
+            }
+            "required"
+                if !value
+                    .as_array()
+                    .is_some_and(|names| names.iter().all(Value::is_string)) =>
+            {
+                return Err(format!("{}: expected an array of property names", at));
+            }
+            "properties" => {
+                let properties = value
+                    .as_object()
+                    .ok_or_else(|| format!("{}: expected an object", at))?;
+                for (name, schema) in properties {
+                    check_schema(schema, &format!("{}/{}", at, name))?;
+                }
+            }
+            "additionalProperties" | "items" | "not" => check_schema(value, &at)?,
+            "allOf" | "anyOf" | "oneOf" => {
+                let schemas = value
+                    .as_array()
+                    .filter(|schemas| !schemas.is_empty())
+                    .ok_or_else(|| format!("{}: expected a non-empty array", at))?;
+                for (index, schema) in schemas.iter().enumerate() {
+                    check_schema(schema, &format!("{}/{}", at, index))?;
+                }
+            }
+            "minItems" | "maxItems" | "minLength" | "maxLength" if !value.is_u64() => {
+                return Err(format!("{}: expected a non-negative integer", at));
+            }
+            "minimum" | "maximum" | "exclusiveMinimum" | "exclusiveMaximum"
+                if !value.is_number() =>
+            {
+                return Err(format!("{}: expected a number", at));
+            }
+            "uniqueItems" if !value.is_boolean() => {
+                return Err(format!("{}: expected a boolean", at));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(n) if n.is_i64() || n.is_u64() => "integer",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn has_type(value: &Value, name: &str) -> bool {
+    match name {
+        // Integral floats such as 1.0 are integers too
+        "integer" => value.as_f64().is_some_and(|n| n.fract() == 0.0),
+        "number" => value.is_number(),
+        name => type_name(value) == name,
+    }
+}
+
+/// Validate `instance` against a schema already accepted by [`check_schema`].
+/// `at` is the instance's JSONPath, e.g. `$.items[0].id`.
+fn validate(schema: &Value, instance: &Value, at: &str) -> Result<(), String> {
+    let schema = match schema {
+        Value::Bool(true) => return Ok(()),
+        Value::Bool(false) => return Err(format!("{}: not allowed", at)),
+        Value::Object(schema) => schema,
+        _ => return Ok(()),
+    };
+
+    if let Some(expected) = schema.get("type") {
+        let names: Vec<&str> = match expected {
+            Value::Array(names) => names.iter().filter_map(Value::as_str).collect(),
+            name => name.as_str().into_iter().collect(),
+        };
+        if !names.iter().any(|name| has_type(instance, name)) {
+            return Err(format!(
+                "{}: expected {}, got {}",
+                at,
+                names.join(" or "),
+                type_name(instance)
+            ));
+        }
+    }
+    if let Some(Value::Array(allowed)) = schema.get("enum") {
+        if !allowed.contains(instance) {
+            return Err(format!(
+                "{}: {} is not one of {}",
+                at,
+                instance,
+                Value::from(allowed.clone())
+            ));
+        }
+    }
+    if let Some(expected) = schema.get("const") {
+        if expected != instance {
+            return Err(format!("{}: expected {}, got {}", at, expected, instance));
+        }
+    }
+
+    match instance {
+        Value::Object(object) => validate_object(schema, object, at)?,
+        Value::Array(items) => validate_array(schema, items, at)?,
+        Value::Number(_) => validate_number(schema, instance.as_f64().unwrap_or(0.0), at)?,
+        Value::String(text) => {
+            let length = text.chars().count() as u64;
+            if let Some(min) = schema.get("minLength").and_then(Value::as_u64) {
+                if length < min {
+                    return Err(format!("{}: shorter than {} characters", at, min));
+                }
+            }
+            if let Some(max) = schema.get("maxLength").and_then(Value::as_u64) {
+                if length > max {
+                    return Err(format!("{}: longer than {} characters", at, max));
+                }
+            }
+        }
+        _ => {}
+    }
+
+    if let Some(Value::Array(schemas)) = schema.get("allOf") {
+        for subschema in schemas {
+            validate(subschema, instance, at)?;
+        }
+    }
+    if let Some(Value::Array(schemas)) = schema.get("anyOf") {
+        if !schemas.iter().any(|s| validate(s, instance, at).is_ok()) {
+            return Err(format!("{}: matches none of anyOf", at));
+        }
+    }
+    if let Some(Value::Array(schemas)) = schema.get("oneOf") {
+        let matching = schemas
+            .iter()
+            .filter(|s| validate(s, instance, at).is_ok())
+            .count();
+        if matching != 1 {
+            return Err(format!(
+                "{}: matches {} of oneOf, expected exactly 1",
+                at, matching
+            ));
+        }
+    }
+    if let Some(not) = schema.get("not") {
+        if validate(not, instance, at).is_ok() {
+            return Err(format!("{}: matches a schema it must not", at));
+        }
+    }
+    Ok(())
+}
+
+fn validate_object(
+    schema: &Map<String, Value>,
+    object: &Map<String, Value>,
+    at: &str,
+) -> Result<(), String> {
+    if let Some(Value::Array(required)) = schema.get("required") {
+        for name in required.iter().filter_map(Value::as_str) {
+            if !object.contains_key(name) {
+                return Err(format!("{}: missing required property '{}'", at, name));
+            }
+        }
+    }
+
+    let properties = schema.get("properties").and_then(Value::as_object);
+    for (name, value) in object {
+        let at = format!("{}.{}", at, name);
+        match properties.and_then(|properties| properties.get(name)) {
+            Some(property) => validate(property, value, &at)?,
+            None => {
+                if let Some(additional) = schema.get("additionalProperties") {
+                    if additional == &Value::Bool(false) {
+                        return Err(format!("{}: unexpected property", at));
+                    }
+                    validate(additional, value, &at)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn validate_array(schema: &Map<String, Value>, items: &[Value], at: &str) -> Result<(), String> {
+    let count = items.len() as u64;
+    if let Some(min) = schema.get("minItems").and_then(Value::as_u64) {
+        if count < min {
+            return Err(format!("{}: fewer than {} items", at, min));
+        }
+    }
+    if let Some(max) = schema.get("maxItems").and_then(Value::as_u64) {
+        if count > max {
+            return Err(format!("{}: more than {} items", at, max));
+        }
+    }
+    if schema.get("uniqueItems") == Some(&Value::Bool(true)) {
+        for (index, item) in items.iter().enumerate() {
+            if items[..index].contains(item) {
+                return Err(format!("{}[{}]: duplicate item", at, index));
+            }
+        }
+    }
+    if let Some(item_schema) = schema.get("items") {
+        for (index, item) in items.iter().enumerate() {
+            validate(item_schema, item, &format!("{}[{}]", at, index))?;
+        }
+    }
+    Ok(())
+}
+
+fn validate_number(schema: &Map<String, Value>, number: f64, at: &str) -> Result<(), String> {
+    let bound = |keyword: &str| schema.get(keyword).and_then(Value::as_f64);
+    if let Some(min) = bound("minimum").filter(|&min| number < min) {
+        return Err(format!("{}: {} is less than {}", at, number, min));
+    }
+    if let Some(max) = bound("maximum").filter(|&max| number > max) {
+        return Err(format!("{}: {} is greater than {}", at, number, max));
+    }
+    if let Some(min) = bound("exclusiveMinimum").filter(|&min| number <= min) {
+        return Err(format!("{}: {} is not greater than {}", at, number, min));
+    }
+    if let Some(max) = bound("exclusiveMaximum").filter(|&max| number >= max) {
+        return Err(format!("{}: {} is not less than {}", at, number, max));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn user_schema() -> ResponseSchema {
+        ResponseSchema::compile(json!({
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "type": "object",
+            "required": ["id", "name"],
+            "properties": {
+                "id": { "type": "integer", "minimum": 1 },
+                "name": { "type": "string", "minLength": 1 },
+                "role": { "enum": ["admin", "user"] },
+                "tags": { "type": "array", "items": { "type": "string" }, "maxItems": 3 }
+            },
+            "additionalProperties": false
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_valid_body_passes() {
+        let body = br#"{"id": 7, "name": "ada", "role": "admin", "tags": ["a", "b"]}"#;
+        assert_eq!(user_schema().validate(body), Ok(()));
+    }
+
+    #[test]
+    fn test_violations_name_the_path() {
+        let schema = user_schema();
+        let check = |body: &str| schema.validate(body.as_bytes()).unwrap_err();
+
+        assert_eq!(check(r#"{"id": 7}"#), "$: missing required property 'name'");
+        assert_eq!(
+            check(r#"{"id": "7", "name": "ada"}"#),
+            "$.id: expected integer, got string"
+        );
+        assert_eq!(
+            check(r#"{"id": 7, "name": "ada", "tags": ["a", 2]}"#),
+            "$.tags[1]: expected string, got integer"
+        );
+        assert_eq!(
+            check(r#"{"id": 0, "name": "ada"}"#),
+            "$.id: 0 is less than 1"
+        );
+        assert_eq!(
+            check(r#"{"id": 7, "name": "ada", "extra": true}"#),
+            "$.extra: unexpected property"
+        );
+        assert!(check("not json").starts_with("body is not JSON"));
+    }
+
+    #[test]
+    fn test_combinators() {
+        let schema = ResponseSchema::compile(json!({
+            "oneOf": [{ "type": "string" }, { "type": "integer" }],
+            "not": { "const": 0 }
+        }))
+        .unwrap();
+
+        assert!(schema.validate(b"\"x\"").is_ok());
+        assert!(schema.validate(b"3").is_ok());
+        assert!(schema.validate(b"0").is_err());
+        assert!(schema.validate(b"null").is_err());
+    }
+
+    #[test]
+    fn test_unsupported_keywords_are_rejected() {
+        let err = ResponseSchema::compile(json!({
+            "properties": { "id": { "$ref": "#/$defs/id" } }
+        }))
+        .unwrap_err();
+        assert_eq!(err, "#/properties/id/$ref: unsupported keyword '$ref'");
+
+        assert!(ResponseSchema::compile(json!({ "type": "float" })).is_err());
+        assert!(ResponseSchema::compile(json!({ "required": "id" })).is_err());
+    }
+}
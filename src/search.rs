@@ -0,0 +1,167 @@
+use std::time::Duration;
+
+/// Steps after which the search stops even if it hasn't converged.
+pub const MAX_SEARCH_STEPS: usize = 10;
+
+/// The search has converged once the highest sustained and lowest failed
+/// rates are within this fraction of each other.
+const CONVERGENCE: f64 = 0.05;
+
+/// A step only counts when the workers actually reached this fraction of the
+/// requested rate; otherwise they were saturated and the latency says nothing.
+const MIN_ACHIEVED_RATIO: f64 = 0.9;
+
+/// One burst of the search and how it went.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchStep {
+    /// Requested requests per second
+    pub rate: f64,
+    /// Requests per second actually completed
+    pub achieved: f64,
+    /// `None` when too few requests completed for a p99
+    pub p99: Option<Duration>,
+    pub max: Option<Duration>,
+    pub sustained: bool,
+}
+
+/// Finds the highest request rate whose p99 latency stays within a target:
+/// the rate doubles until a step fails, then the interval between the highest
+/// sustained and the lowest failed rate is bisected.
+#[derive(Debug)]
+pub struct RateSearch {
+    target_p99: Duration,
+    start_rate: f64,
+    /// Highest rate that was sustained so far
+    sustained: Option<f64>,
+    /// Lowest rate that failed so far
+    failed: Option<f64>,
+    pub steps: Vec<SearchStep>,
+}
+
+impl RateSearch {
+    pub fn new(target_p99: Duration, start_rate: f64) -> Self {
+        Self {
+            target_p99,
+            start_rate,
+            sustained: None,
+            failed: None,
+            steps: Vec::new(),
+        }
+    }
+
+    /// The rate to try next, or `None` once the search is done.
+    pub fn next_rate(&self) -> Option<f64> {
+        if self.steps.len() >= MAX_SEARCH_STEPS {
+            return None;
+        }
+        match (self.sustained, self.failed) {
+            (None, None) => Some(self.start_rate),
+            (Some(sustained), None) => Some(sustained * 2.0),
+            (sustained, Some(failed)) => {
+                let sustained = sustained.unwrap_or(0.0);
+                if failed - sustained <= failed * CONVERGENCE {
+                    return None;
+                }
+                Some((sustained + failed) / 2.0)
+            }
+        }
+    }
+
+    /// Record how a burst at `rate` went. Without enough samples for a p99,
+    /// the maximum latency has to stay within the target instead.
+    pub fn record(
+        &mut self,
+        rate: f64,
+        achieved: f64,
+        p99: Option<Duration>,
+        max: Option<Duration>,
+    ) {
+        let sustained = p99.or(max).is_some_and(|p99| p99 <= self.target_p99)
+            && achieved >= rate * MIN_ACHIEVED_RATIO;
+        if sustained {
+            self.sustained = Some(self.sustained.map_or(rate, |s| s.max(rate)));
+        } else {
+            self.failed = Some(self.failed.map_or(rate, |f| f.min(rate)));
+        }
+        self.steps.push(SearchStep {
+            rate,
+            achieved,
+            p99,
+            max,
+            sustained,
+        });
+    }
+
+    /// The highest rate sustained within the target, if any was.
+    pub fn result(&self) -> Option<f64> {
+        self.sustained
+    }
+}
+
+/// Start-to-start gap that makes `workers` persistent workers send `rate`
+/// requests per second between them.
+pub fn gap_for_rate(workers: u32, rate: f64) -> Duration {
+    Duration::from_secs_f64(workers as f64 / rate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TARGET: Duration = Duration::from_millis(100);
+
+    /// Drive a search against a server whose p99 exceeds the target above `capacity`.
+    fn run(capacity: f64) -> RateSearch {
+        let mut search = RateSearch::new(TARGET, 10.0);
+        while let Some(rate) = search.next_rate() {
+            let p99 = if rate <= capacity {
+                Duration::from_millis(50)
+            } else {
+                Duration::from_millis(500)
+            };
+            search.record(rate, rate, Some(p99), Some(p99));
+        }
+        search
+    }
+
+    #[test]
+    fn test_search_converges_on_capacity() {
+        let search = run(100.0);
+
+        let rates: Vec<f64> = search.steps.iter().take(5).map(|s| s.rate).collect();
+        assert_eq!(rates, vec![10.0, 20.0, 40.0, 80.0, 160.0]);
+        let result = search.result().unwrap();
+        assert!((90.0..=100.0).contains(&result), "{}", result);
+        assert!(search.steps.len() <= MAX_SEARCH_STEPS);
+    }
+
+    #[test]
+    fn test_search_below_start_rate() {
+        let search = run(3.0);
+        assert!(search.steps[0].rate == 10.0 && !search.steps[0].sustained);
+        assert!(search.result().unwrap() <= 3.0);
+    }
+
+    #[test]
+    fn test_saturated_workers_do_not_sustain_a_rate() {
+        let mut search = RateSearch::new(TARGET, 10.0);
+        search.record(10.0, 5.0, Some(Duration::from_millis(10)), None);
+        assert!(!search.steps[0].sustained);
+        assert_eq!(search.next_rate(), Some(5.0));
+    }
+
+    #[test]
+    fn test_max_latency_stands_in_for_missing_p99() {
+        let mut search = RateSearch::new(TARGET, 10.0);
+        search.record(10.0, 10.0, None, Some(Duration::from_millis(20)));
+        search.record(20.0, 20.0, None, None);
+
+        assert!(search.steps[0].sustained);
+        assert!(!search.steps[1].sustained);
+    }
+
+    #[test]
+    fn test_gap_for_rate() {
+        assert_eq!(gap_for_rate(5, 50.0), Duration::from_millis(100));
+    }
+}
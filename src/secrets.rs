@@ -0,0 +1,172 @@
+use std::fs;
+
+/// Read a credential from `path`, dropping surrounding whitespace such as the
+/// trailing newline most editors add.
+pub fn read_secret_file(path: &str) -> Result<String, String> {
+    let content =
+        fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let secret = content.trim();
+    if secret.is_empty() {
+        return Err(format!("{} is empty", path));
+    }
+    Ok(secret.to_string())
+}
+
+/// Parse a header file: one `Name: value` per line, skipping blank lines and
+/// `#` comments.
+pub fn parse_header_file(content: &str) -> Result<Vec<(String, String)>, String> {
+    content
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(index, line)| split_header(line).map_err(|e| format!("line {}: {}", index + 1, e)))
+        .collect()
+}
+
+/// Parse a `Name: value` header given with --header, echoing it back when
+/// it is malformed.
+pub fn parse_header(spec: &str) -> Result<(String, String), String> {
+    split_header(spec).map_err(|e| format!("Invalid header '{}': {}", spec, e))
+}
+
+fn split_header(line: &str) -> Result<(String, String), String> {
+    let (name, value) = line
+        .split_once(':')
+        .ok_or_else(|| "expected Name: value".to_string())?;
+    let name = name.trim();
+    if reqwest::header::HeaderName::from_bytes(name.as_bytes()).is_err() {
+        return Err(format!("invalid header name '{}'", name));
+    }
+    Ok((name.to_string(), value.trim().to_string()))
+}
+
+/// Headers read from the credential files given on the command line. The
+/// files are read once, at startup.
+pub fn load_file_headers(
+    bearer_file: Option<&str>,
+    basic_auth_file: Option<&str>,
+    header_file: Option<&str>,
+) -> Result<Vec<(String, String)>, String> {
+    if bearer_file.is_some() && basic_auth_file.is_some() {
+        return Err("Use either a bearer token file or a basic auth file, not both".to_string());
+    }
+
+    let mut headers = Vec::new();
+    if let Some(path) = bearer_file {
+        let token = read_secret_file(path)?;
+        headers.push(("Authorization".to_string(), format!("Bearer {}", token)));
+    }
+    if let Some(path) = basic_auth_file {
+        let credentials = read_secret_file(path)?;
+        if !credentials.contains(':') {
+            return Err(format!("{}: expected user:password", path));
+        }
+        headers.push((
+            "Authorization".to_string(),
+            format!("Basic {}", base64_encode(credentials.as_bytes())),
+        ));
+    }
+    if let Some(path) = header_file {
+        let content =
+            fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+        headers.extend(parse_header_file(&content).map_err(|e| format!("{}: {}", path, e))?);
+    }
+    Ok(headers)
+}
+
+/// Standard base64 with padding, as HTTP Basic authentication expects.
+pub fn base64_encode(input: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut output = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let triple = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                output.push(ALPHABET[(triple >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                output.push('=');
+            }
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_base64_encode() {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foo"), "Zm9v");
+        assert_eq!(base64_encode(b"user:pw"), "dXNlcjpwdw==");
+    }
+
+    #[test]
+    fn test_parse_header_file() {
+        let headers = parse_header_file("# shared secrets\nX-Api-Key: abc\n\nX-Tenant:  acme \n");
+        assert_eq!(
+            headers,
+            Ok(vec![
+                ("X-Api-Key".to_string(), "abc".to_string()),
+                ("X-Tenant".to_string(), "acme".to_string()),
+            ])
+        );
+        assert!(parse_header_file("no colon here").is_err());
+    }
+
+    #[test]
+    fn test_parse_header() {
+        assert_eq!(
+            parse_header("Content-Type:  application/json "),
+            Ok(("Content-Type".to_string(), "application/json".to_string()))
+        );
+        // Only the first colon separates name and value
+        assert_eq!(
+            parse_header("X-Trace: a:b"),
+            Ok(("X-Trace".to_string(), "a:b".to_string()))
+        );
+        assert_eq!(
+            parse_header("Bad Name: 1"),
+            Err("Invalid header 'Bad Name: 1': invalid header name 'Bad Name'".to_string())
+        );
+    }
+
+    #[test]
+    fn test_load_file_headers() {
+        let mut token = NamedTempFile::new().unwrap();
+        writeln!(token, "tok123").unwrap();
+        let mut basic = NamedTempFile::new().unwrap();
+        writeln!(basic, "user:pw").unwrap();
+        let token_path = token.path().to_str().unwrap();
+
+        assert_eq!(
+            load_file_headers(Some(token_path), None, None),
+            Ok(vec![(
+                "Authorization".to_string(),
+                "Bearer tok123".to_string()
+            )])
+        );
+        assert_eq!(
+            load_file_headers(None, Some(basic.path().to_str().unwrap()), None),
+            Ok(vec![(
+                "Authorization".to_string(),
+                "Basic dXNlcjpwdw==".to_string()
+            )])
+        );
+
+        let empty = NamedTempFile::new().unwrap();
+        assert!(load_file_headers(Some(empty.path().to_str().unwrap()), None, None).is_err());
+    }
+}
@@ -0,0 +1,148 @@
+use reqwest::header::HeaderMap;
+use std::time::Duration;
+
+use crate::stats::LatencyRecorder;
+
+const SERVER_TIMING: &str = "server-timing";
+
+/// Client latency, server-reported time and the difference between the two,
+/// for the responses of a --parse-server-timing run that reported a time.
+pub struct ServerTimings {
+    pub client: LatencyRecorder,
+    pub server: LatencyRecorder,
+    /// Client latency minus server time: the network, TLS and queueing
+    /// outside the server's own measurement
+    pub overhead: LatencyRecorder,
+    /// Responses without a `dur` in any Server-Timing header
+    pub missing: u64,
+}
+
+impl Default for ServerTimings {
+    fn default() -> Self {
+        Self {
+            client: LatencyRecorder::new(None),
+            server: LatencyRecorder::new(None),
+            overhead: LatencyRecorder::new(None),
+            missing: 0,
+        }
+    }
+}
+
+impl ServerTimings {
+    /// Count a response that took `latency` as seen by the client.
+    pub fn record(&mut self, headers: &HeaderMap, latency: Duration) {
+        let values = headers
+            .get_all(SERVER_TIMING)
+            .iter()
+            .filter_map(|value| value.to_str().ok());
+        match server_duration(values) {
+            Some(server) => {
+                self.client.record(latency);
+                self.server.record(server);
+                self.overhead.record(latency.saturating_sub(server));
+            }
+            None => self.missing += 1,
+        }
+    }
+}
+
+/// The server's processing time from the values of its Server-Timing
+/// headers: the `dur` of a metric named `total` if there is one, otherwise
+/// the sum of every metric's `dur`. `None` when no metric has a `dur`, or
+/// when the time is too large to be a duration.
+pub fn server_duration<'a>(values: impl IntoIterator<Item = &'a str>) -> Option<Duration> {
+    let mut total = None;
+    let mut sum: Option<f64> = None;
+    for value in values {
+        for metric in split_unquoted(value, ',') {
+            let mut parts = split_unquoted(metric, ';').into_iter();
+            let name = parts.next().unwrap_or_default().trim();
+            let dur = parts.find_map(|param| {
+                let (key, value) = param.split_once('=')?;
+                if !key.trim().eq_ignore_ascii_case("dur") {
+                    return None;
+                }
+                let ms: f64 = value.trim().trim_matches('"').parse().ok()?;
+                (ms >= 0.0 && ms.is_finite()).then_some(ms)
+            });
+            if let Some(ms) = dur {
+                if name.eq_ignore_ascii_case("total") {
+                    total.get_or_insert(ms);
+                }
+                *sum.get_or_insert(0.0) += ms;
+            }
+        }
+    }
+    total
+        .or(sum)
+        .and_then(|ms| Duration::try_from_secs_f64(ms / 1000.0).ok())
+}
+
+/// Split on `separator` outside double quotes, since a `desc` may contain
+/// commas and semicolons.
+fn split_unquoted(value: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut quoted = false;
+    let mut escaped = false;
+    let mut start = 0;
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            c if c == separator && !quoted => {
+                parts.push(&value[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&value[start..]);
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_server_duration() {
+        assert_eq!(
+            server_duration(["db;dur=53, app;dur=47.2"]),
+            Some(Duration::from_micros(100_200))
+        );
+        // A total wins over the parts it covers
+        assert_eq!(
+            server_duration(["db;dur=53", "total;dur=120, app;dur=47.2"]),
+            Some(Duration::from_millis(120))
+        );
+        // Separators inside a description don't split the metric
+        assert_eq!(
+            server_duration([r#"cache;desc="Cache, Read; \"hot\"";dur=23.5"#]),
+            Some(Duration::from_micros(23_500))
+        );
+        assert_eq!(server_duration(["miss, cdn;desc=edge"]), None);
+        assert_eq!(server_duration(["app;dur=-1, db;dur=abc"]), None);
+        assert_eq!(server_duration([]), None);
+        // Absurd values are missing rather than fatal
+        assert_eq!(server_duration(["app;dur=1e30"]), None);
+        assert_eq!(server_duration(["app;dur=1e308, db;dur=1e308"]), None);
+    }
+
+    #[test]
+    fn test_record() {
+        let mut timings = ServerTimings::default();
+        let mut headers = HeaderMap::new();
+        headers.insert(SERVER_TIMING, "app;dur=30".parse().unwrap());
+        timings.record(&headers, Duration::from_millis(50));
+        // A server time above the client's latency leaves no overhead
+        timings.record(&headers, Duration::from_millis(20));
+        timings.record(&HeaderMap::new(), Duration::from_millis(50));
+
+        assert_eq!(timings.server.count(), 2);
+        assert_eq!(timings.client.count(), 2);
+        assert_eq!(timings.missing, 1);
+        assert_eq!(timings.overhead.histogram().max() / 1000, 20);
+        assert_eq!(timings.overhead.histogram().min(), 1);
+    }
+}
@@ -0,0 +1,97 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Samples closer together than this are skipped: a rate over a sliver of
+/// time, such as the interval's immediate first tick, is mostly noise.
+const MIN_SAMPLE_SPAN: Duration = Duration::from_millis(500);
+
+const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Requests per second over the most recent progress ticks, for a sparkline
+/// in the status line.
+pub struct RateHistory {
+    window: usize,
+    rates: VecDeque<f64>,
+    last: Option<(Instant, u32)>,
+}
+
+impl RateHistory {
+    /// Keep the rates of the last `window` ticks.
+    pub fn new(window: usize) -> Self {
+        Self {
+            window,
+            rates: VecDeque::with_capacity(window),
+            last: None,
+        }
+    }
+
+    /// Record the running total of completed requests at `now`. The rate is
+    /// taken over the actual time since the previous sample, since a tick can
+    /// be late when a batch of requests is slow.
+    pub fn sample(&mut self, now: Instant, completed: u32) {
+        if let Some((at, previous)) = self.last {
+            let span = now.duration_since(at);
+            if span < MIN_SAMPLE_SPAN {
+                return;
+            }
+            if self.rates.len() == self.window {
+                self.rates.pop_front();
+            }
+            self.rates
+                .push_back(completed.saturating_sub(previous) as f64 / span.as_secs_f64());
+        }
+        self.last = Some((now, completed));
+    }
+
+    pub fn latest(&self) -> Option<f64> {
+        self.rates.back().copied()
+    }
+
+    pub fn sparkline(&self) -> String {
+        let rates: Vec<f64> = self.rates.iter().copied().collect();
+        sparkline(&rates)
+    }
+}
+
+/// One bar per value, scaled so the largest value gets the tallest bar.
+pub fn sparkline(values: &[f64]) -> String {
+    let max = values.iter().copied().fold(0.0, f64::max);
+    values
+        .iter()
+        .map(|&value| {
+            if max == 0.0 {
+                return BARS[0];
+            }
+            let level = (value / max * (BARS.len() - 1) as f64).round() as usize;
+            BARS[level.min(BARS.len() - 1)]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sparkline_scales_to_max() {
+        assert_eq!(sparkline(&[0.0, 50.0, 100.0]), "▁▅█");
+        assert_eq!(sparkline(&[0.0, 0.0]), "▁▁");
+        assert_eq!(sparkline(&[]), "");
+    }
+
+    #[test]
+    fn test_rate_history_keeps_window() {
+        let start = Instant::now();
+        let mut history = RateHistory::new(2);
+        history.sample(start, 0);
+        history.sample(start + Duration::from_millis(10), 5);
+        assert_eq!(history.latest(), None);
+
+        for (second, total) in [(1, 10), (2, 30), (4, 30)] {
+            history.sample(start + Duration::from_secs(second), total);
+        }
+
+        assert_eq!(history.rates, VecDeque::from([20.0, 0.0]));
+        assert_eq!(history.sparkline(), "█▁");
+    }
+}
@@ -0,0 +1,576 @@
+use hdrhistogram::Histogram;
+use rand::Rng;
+use std::time::Duration;
+
+/// Highest latency the histogram can track: one hour, in microseconds.
+const HISTOGRAM_MAX_MICROS: u64 = 3_600_000_000;
+
+/// Records every latency into an HdrHistogram (constant memory, bounded
+/// relative error) and optionally keeps the raw samples as well.
+pub struct LatencyRecorder {
+    histogram: Histogram<u64>,
+    raw: Option<LatencySamples>,
+}
+
+impl LatencyRecorder {
+    pub fn new(raw: Option<LatencySamples>) -> Self {
+        Self {
+            histogram: Histogram::new_with_bounds(1, HISTOGRAM_MAX_MICROS, 3)
+                .expect("valid histogram bounds"),
+            raw,
+        }
+    }
+
+    pub fn record(&mut self, latency: Duration) {
+        let micros = latency.as_micros().min(u64::MAX as u128) as u64;
+        self.histogram.saturating_record(micros.max(1));
+
+        if let Some(raw) = &mut self.raw {
+            raw.record(latency);
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.histogram.len()
+    }
+
+    pub fn histogram(&self) -> &Histogram<u64> {
+        &self.histogram
+    }
+
+    pub fn raw(&self) -> Option<&LatencySamples> {
+        self.raw.as_ref()
+    }
+
+    /// Add the latencies of `other` to the histogram. Raw samples are left
+    /// as they are, since a reservoir can't be merged without bias.
+    pub fn merge(&mut self, other: &LatencyRecorder) {
+        self.histogram
+            .add(&other.histogram)
+            .expect("histograms share their bounds");
+    }
+}
+
+/// Latency samples kept for the summary report.
+///
+/// When a capacity is set, the samples are maintained with reservoir sampling
+/// (Algorithm R) so they remain a uniform random subset of every latency seen,
+/// no matter how long the run goes on.
+pub struct LatencySamples {
+    samples: Vec<Duration>,
+    capacity: Option<usize>,
+    seen: u64,
+}
+
+impl LatencySamples {
+    pub fn new(capacity: Option<usize>) -> Self {
+        Self {
+            samples: Vec::new(),
+            capacity,
+            seen: 0,
+        }
+    }
+
+    pub fn record(&mut self, latency: Duration) {
+        self.record_with(latency, &mut rand::rng());
+    }
+
+    fn record_with<R: Rng>(&mut self, latency: Duration, rng: &mut R) {
+        self.seen += 1;
+
+        match self.capacity {
+            Some(capacity) if self.samples.len() >= capacity => {
+                let slot = rng.random_range(0..self.seen);
+                if slot < capacity as u64 {
+                    self.samples[slot as usize] = latency;
+                }
+            }
+            _ => self.samples.push(latency),
+        }
+    }
+
+    pub fn samples(&self) -> &[Duration] {
+        &self.samples
+    }
+
+    /// Total number of latencies recorded, including those not retained.
+    pub fn seen(&self) -> u64 {
+        self.seen
+    }
+}
+
+/// Fewest samples for which the `p`th percentile is more than just the
+/// maximum, e.g. 20 for p95 and 100 for p99.
+pub fn min_samples_for_percentile(p: f64) -> u64 {
+    if p >= 100.0 {
+        return u64::MAX;
+    }
+    exact_ceil(100.0 / (100.0 - p)) as u64
+}
+
+/// Whether `count` samples are enough to report the `p`th percentile.
+pub fn has_enough_samples(count: u64, p: f64) -> bool {
+    count >= min_samples_for_percentile(p)
+}
+
+/// Apdex counts against a target time T: a request is satisfied within T,
+/// tolerating within 4T and frustrated beyond that or when it failed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Apdex {
+    pub threshold: Duration,
+    pub satisfied: u64,
+    pub tolerating: u64,
+    pub frustrated: u64,
+}
+
+impl Apdex {
+    pub fn new(threshold: Duration) -> Self {
+        Self {
+            threshold,
+            satisfied: 0,
+            tolerating: 0,
+            frustrated: 0,
+        }
+    }
+
+    /// Count a successful request that took `latency`.
+    pub fn record(&mut self, latency: Duration) {
+        if latency <= self.threshold {
+            self.satisfied += 1;
+        } else if latency <= self.threshold * 4 {
+            self.tolerating += 1;
+        } else {
+            self.frustrated += 1;
+        }
+    }
+
+    pub fn record_failure(&mut self) {
+        self.frustrated += 1;
+    }
+
+    /// `(satisfied + tolerating / 2) / total`, from 0 (all frustrated) to 1
+    /// (all satisfied); `None` before any request was counted.
+    pub fn score(&self) -> Option<f64> {
+        let total = self.satisfied + self.tolerating + self.frustrated;
+        if total == 0 {
+            return None;
+        }
+        Some((self.satisfied as f64 + self.tolerating as f64 / 2.0) / total as f64)
+    }
+}
+
+/// Requests checked against --hard-deadline: any that took longer is an SLA
+/// violation, whether it succeeded or not.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DeadlineCheck {
+    pub deadline: Duration,
+    pub requests: u64,
+    pub violations: u64,
+}
+
+impl DeadlineCheck {
+    pub fn new(deadline: Duration) -> Self {
+        Self {
+            deadline,
+            requests: 0,
+            violations: 0,
+        }
+    }
+
+    pub fn record(&mut self, latency: Duration) {
+        self.requests += 1;
+        if latency > self.deadline {
+            self.violations += 1;
+        }
+    }
+
+    /// Percentage of requests over the deadline; `None` before any request
+    /// was counted.
+    pub fn violation_rate(&self) -> Option<f64> {
+        (self.requests > 0).then(|| self.violations as f64 / self.requests as f64 * 100.0)
+    }
+}
+
+/// Requests in flight, sampled at a fixed interval through a run: the
+/// parallelism the run actually reached, as opposed to the one asked for.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct InFlightSamples {
+    pub samples: u64,
+    pub sum: u64,
+    pub min: u32,
+    pub max: u32,
+}
+
+impl InFlightSamples {
+    pub fn record(&mut self, in_flight: u32) {
+        self.min = if self.samples == 0 {
+            in_flight
+        } else {
+            self.min.min(in_flight)
+        };
+        self.max = self.max.max(in_flight);
+        self.samples += 1;
+        self.sum += in_flight as u64;
+    }
+
+    /// Average requests in flight; `None` before any sample.
+    pub fn avg(&self) -> Option<f64> {
+        (self.samples > 0).then(|| self.sum as f64 / self.samples as f64)
+    }
+}
+
+/// A yield between spawn batches counts as stalled when the scheduler took
+/// longer than this to hand control back.
+pub const SPAWN_STALL_THRESHOLD: Duration = Duration::from_millis(10);
+
+/// Yields between batches of spawned requests: how often the scheduler was
+/// too busy to get back to the dispatch loop promptly.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpawnStalls {
+    pub batch_size: u32,
+    pub yields: u64,
+    /// Yields that took longer than [`SPAWN_STALL_THRESHOLD`]
+    pub stalled: u64,
+    pub longest: Duration,
+}
+
+impl SpawnStalls {
+    pub fn new(batch_size: u32) -> Self {
+        Self {
+            batch_size,
+            yields: 0,
+            stalled: 0,
+            longest: Duration::ZERO,
+        }
+    }
+
+    /// Count a yield that took `waited` to return.
+    pub fn record(&mut self, waited: Duration) {
+        self.yields += 1;
+        if waited > SPAWN_STALL_THRESHOLD {
+            self.stalled += 1;
+        }
+        self.longest = self.longest.max(waited);
+    }
+}
+
+/// How closely a --gap run kept to its schedule: the rate it asked for, and
+/// how many dispatches started within `tolerance` of their scheduled time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateAccuracy {
+    /// Requests per second across all workers
+    pub requested: f64,
+    pub tolerance: Duration,
+    pub dispatched: u64,
+    pub on_time: u64,
+}
+
+impl RateAccuracy {
+    pub fn new(requested: f64, tolerance: Duration) -> Self {
+        Self {
+            requested,
+            tolerance,
+            dispatched: 0,
+            on_time: 0,
+        }
+    }
+
+    /// Count a dispatch that started `lateness` after its scheduled time.
+    pub fn record(&mut self, lateness: Duration) {
+        self.dispatched += 1;
+        if lateness <= self.tolerance {
+            self.on_time += 1;
+        }
+    }
+
+    /// Dispatches per second over `elapsed`.
+    pub fn achieved(&self, elapsed: Duration) -> f64 {
+        let secs = elapsed.as_secs_f64();
+        if secs == 0.0 {
+            return 0.0;
+        }
+        self.dispatched as f64 / secs
+    }
+
+    /// Percentage of dispatches that started on time; `None` before any.
+    pub fn on_time_percent(&self) -> Option<f64> {
+        (self.dispatched > 0).then(|| self.on_time as f64 * 100.0 / self.dispatched as f64)
+    }
+}
+
+/// Completed requests per target URL, checked against --min-per-url.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UrlSamples {
+    pub minimum: u32,
+    /// One entry per target, in target order
+    pub counts: Vec<(String, u32)>,
+}
+
+impl UrlSamples {
+    pub fn new(urls: impl IntoIterator<Item = String>, minimum: u32) -> Self {
+        Self {
+            minimum,
+            counts: urls.into_iter().map(|url| (url, 0)).collect(),
+        }
+    }
+
+    /// Count a completed request to the target at `index`.
+    pub fn record(&mut self, index: usize) {
+        if let Some((_, count)) = self.counts.get_mut(index) {
+            *count += 1;
+        }
+    }
+
+    /// Targets that completed fewer requests than the minimum.
+    pub fn short(&self) -> Vec<&(String, u32)> {
+        self.counts
+            .iter()
+            .filter(|(_, count)| *count < self.minimum)
+            .collect()
+    }
+}
+
+/// Requests and latencies of one target in a weighted `--url` mix.
+pub struct UrlStats {
+    pub url: String,
+    pub weight: f64,
+    /// Latencies of successful requests, timed like the aggregate ones:
+    /// the successful attempt only
+    pub latency: LatencyRecorder,
+    pub failed: u32,
+}
+
+impl UrlStats {
+    pub fn new(url: String, weight: f64) -> Self {
+        Self {
+            url,
+            weight,
+            latency: LatencyRecorder::new(None),
+            failed: 0,
+        }
+    }
+}
+
+/// Ceiling of `value`, ignoring floating point error: fractional
+/// percentiles such as 99.9 don't divide exactly, and 1000.0000000000001
+/// must still round to 1000.
+fn exact_ceil(value: f64) -> f64 {
+    ((value * 1e6).round() / 1e6).ceil()
+}
+
+/// Nearest-rank percentile of an ascending, non-empty slice.
+pub fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let rank = exact_ceil(p / 100.0 * sorted.len() as f64) as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Median of an ascending, non-empty slice: the middle sample, or the mean
+/// of the two middle samples when there is an even number of them.
+pub fn median(sorted: &[Duration]) -> Duration {
+    let mid = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        (sorted[mid - 1] + sorted[mid]) / 2
+    } else {
+        sorted[mid]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_rate_accuracy() {
+        let mut accuracy = RateAccuracy::new(10.0, Duration::from_millis(5));
+        assert_eq!(accuracy.on_time_percent(), None);
+
+        accuracy.record(Duration::ZERO);
+        accuracy.record(Duration::from_millis(5));
+        accuracy.record(Duration::from_millis(3));
+        accuracy.record(Duration::from_millis(40));
+
+        assert_eq!(accuracy.on_time_percent(), Some(75.0));
+        assert_eq!(accuracy.achieved(Duration::from_secs(2)), 2.0);
+    }
+
+    #[test]
+    fn test_url_samples_short() {
+        let urls = ["https://a.example.com", "https://b.example.com"];
+        let mut samples = UrlSamples::new(urls.map(String::from), 2);
+        samples.record(0);
+        samples.record(0);
+        samples.record(1);
+        samples.record(7);
+
+        assert_eq!(
+            samples.short(),
+            vec![&("https://b.example.com".to_string(), 1)]
+        );
+        samples.record(1);
+        assert!(samples.short().is_empty());
+    }
+
+    #[test]
+    fn test_deadline_check() {
+        let mut check = DeadlineCheck::new(Duration::from_millis(100));
+        assert_eq!(check.violation_rate(), None);
+
+        check.record(Duration::from_millis(40));
+        check.record(Duration::from_millis(100));
+        check.record(Duration::from_millis(101));
+        check.record(Duration::from_secs(3));
+        assert_eq!(check.requests, 4);
+        assert_eq!(check.violations, 2);
+        assert_eq!(check.violation_rate(), Some(50.0));
+    }
+
+    #[test]
+    fn test_in_flight_samples() {
+        let mut samples = InFlightSamples::default();
+        assert_eq!(samples.avg(), None);
+
+        for in_flight in [50, 12, 0, 34] {
+            samples.record(in_flight);
+        }
+        assert_eq!((samples.min, samples.max), (0, 50));
+        assert_eq!(samples.avg(), Some(24.0));
+    }
+
+    #[test]
+    fn test_apdex_score() {
+        let mut apdex = Apdex::new(Duration::from_millis(100));
+        assert_eq!(apdex.score(), None);
+
+        for ms in [50, 100, 100, 150, 400, 401] {
+            apdex.record(Duration::from_millis(ms));
+        }
+        apdex.record_failure();
+        apdex.record(Duration::from_millis(10));
+
+        assert_eq!(
+            (apdex.satisfied, apdex.tolerating, apdex.frustrated),
+            (4, 2, 2)
+        );
+        assert_eq!(apdex.score(), Some(0.625));
+    }
+
+    #[test]
+    fn test_unbounded_keeps_every_sample() {
+        let mut samples = LatencySamples::new(None);
+        for ms in 0..100 {
+            samples.record(Duration::from_millis(ms));
+        }
+
+        assert_eq!(samples.samples().len(), 100);
+        assert_eq!(samples.seen(), 100);
+    }
+
+    #[test]
+    fn test_capacity_bounds_stored_samples() {
+        let mut samples = LatencySamples::new(Some(10));
+        let mut rng = StdRng::seed_from_u64(7);
+        for ms in 0..1000 {
+            samples.record_with(Duration::from_millis(ms), &mut rng);
+        }
+
+        assert_eq!(samples.samples().len(), 10);
+        assert_eq!(samples.seen(), 1000);
+    }
+
+    #[test]
+    fn test_reservoir_is_roughly_uniform() {
+        // Feed 0..1000 through a reservoir of 100 many times and check the mean
+        // of the retained values stays near the mean of the full stream.
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut total_mean = 0.0;
+        let rounds = 50;
+
+        for _ in 0..rounds {
+            let mut samples = LatencySamples::new(Some(100));
+            for ms in 0..1000 {
+                samples.record_with(Duration::from_millis(ms), &mut rng);
+            }
+            let sum: u128 = samples.samples().iter().map(|d| d.as_millis()).sum();
+            total_mean += sum as f64 / samples.samples().len() as f64;
+        }
+
+        let mean = total_mean / rounds as f64;
+        assert!((mean - 499.5).abs() < 25.0, "mean was {}", mean);
+    }
+
+    #[test]
+    fn test_percentile_nearest_rank() {
+        let sorted: Vec<Duration> = (1..=10).map(Duration::from_millis).collect();
+
+        assert_eq!(percentile(&sorted, 50.0), Duration::from_millis(5));
+        assert_eq!(percentile(&sorted, 95.0), Duration::from_millis(10));
+        assert_eq!(percentile(&sorted, 0.0), Duration::from_millis(1));
+        assert_eq!(percentile(&sorted, 100.0), Duration::from_millis(10));
+        assert_eq!(percentile(&sorted, 90.0), Duration::from_millis(9));
+        assert_eq!(percentile(&sorted, 99.0), Duration::from_millis(10));
+
+        // A single sample is every percentile
+        let single = [Duration::from_millis(7)];
+        for p in [0.0, 50.0, 90.0, 95.0, 99.0, 100.0] {
+            assert_eq!(percentile(&single, p), Duration::from_millis(7));
+        }
+    }
+
+    #[test]
+    fn test_median() {
+        let ms = Duration::from_millis;
+        assert_eq!(median(&[ms(10), ms(20), ms(40)]), ms(20));
+        assert_eq!(median(&[ms(10), ms(20)]), ms(15));
+        assert_eq!(
+            median(&[ms(1), ms(2), ms(3), ms(10)]),
+            Duration::from_micros(2500)
+        );
+        assert_eq!(median(&[ms(7)]), ms(7));
+    }
+
+    #[test]
+    fn test_recorder_histogram_only() {
+        let mut recorder = LatencyRecorder::new(None);
+        for ms in 1..=100 {
+            recorder.record(Duration::from_millis(ms));
+        }
+
+        assert_eq!(recorder.count(), 100);
+        assert!(recorder.raw().is_none());
+
+        // Three significant digits keeps the p99 within 0.1% of the exact value
+        let p99 = recorder.histogram().value_at_quantile(0.99);
+        assert!((p99 as i64 - 99_000).abs() <= 99, "p99 was {}", p99);
+    }
+
+    #[test]
+    fn test_recorder_keeps_raw_samples() {
+        let mut recorder = LatencyRecorder::new(Some(LatencySamples::new(None)));
+        recorder.record(Duration::from_micros(250));
+        recorder.record(Duration::ZERO);
+
+        assert_eq!(recorder.count(), 2);
+        assert_eq!(recorder.raw().unwrap().samples().len(), 2);
+    }
+
+    #[test]
+    fn test_min_samples_for_percentile() {
+        assert_eq!(min_samples_for_percentile(50.0), 2);
+        assert_eq!(min_samples_for_percentile(95.0), 20);
+        assert_eq!(min_samples_for_percentile(99.0), 100);
+        assert_eq!(min_samples_for_percentile(99.9), 1000);
+        assert_eq!(min_samples_for_percentile(75.0), 4);
+        assert!(has_enough_samples(20, 95.0));
+        assert!(!has_enough_samples(19, 95.0));
+        assert!(!has_enough_samples(1, 50.0));
+    }
+
+    #[test]
+    fn test_empty() {
+        let samples = LatencySamples::new(Some(5));
+        assert!(samples.samples().is_empty());
+        assert_eq!(samples.seen(), 0);
+    }
+}
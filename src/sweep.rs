@@ -0,0 +1,69 @@
+use std::time::Duration;
+
+/// A level's p95 has to exceed the lowest p95 of the levels before it by this
+/// factor before latency counts as climbing.
+const KNEE_FACTOR: f64 = 1.5;
+
+/// How one concurrency level of a --concurrency-sweep went.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SweepPoint {
+    pub concurrency: u32,
+    pub requests_per_second: f64,
+    /// `None` when too few requests completed for a p95
+    pub p95: Option<Duration>,
+    pub error_rate: f64,
+}
+
+/// Index of the knee of the scaling curve: the first level whose p95 exceeds
+/// the best p95 seen at lower levels by [`KNEE_FACTOR`]. Levels without a p95
+/// are skipped, so the knee is `None` until latency actually climbs.
+pub fn knee_point(points: &[SweepPoint]) -> Option<usize> {
+    let mut best: Option<Duration> = None;
+    for (index, point) in points.iter().enumerate() {
+        let Some(p95) = point.p95 else {
+            continue;
+        };
+        if let Some(best) = best {
+            if p95.as_secs_f64() > best.as_secs_f64() * KNEE_FACTOR {
+                return Some(index);
+            }
+        }
+        best = Some(best.map_or(p95, |best| best.min(p95)));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(concurrency: u32, p95_ms: Option<u64>) -> SweepPoint {
+        SweepPoint {
+            concurrency,
+            requests_per_second: concurrency as f64,
+            p95: p95_ms.map(Duration::from_millis),
+            error_rate: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_knee_point() {
+        let points = vec![
+            point(1, Some(20)),
+            point(5, Some(22)),
+            point(10, Some(25)),
+            point(50, Some(40)),
+            point(100, Some(200)),
+        ];
+        assert_eq!(knee_point(&points), Some(3));
+
+        let flat = vec![point(1, Some(20)), point(5, Some(21)), point(10, None)];
+        assert_eq!(knee_point(&flat), None);
+    }
+
+    #[test]
+    fn test_knee_point_skips_levels_without_p95() {
+        let points = vec![point(1, None), point(5, Some(20)), point(10, Some(90))];
+        assert_eq!(knee_point(&points), Some(2));
+    }
+}
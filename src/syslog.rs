@@ -0,0 +1,86 @@
+use std::io;
+
+/// Local syslog sockets, tried in order: Linux, then macOS, then the BSDs.
+#[cfg(unix)]
+const SOCKET_PATHS: [&str; 3] = ["/dev/log", "/var/run/syslog", "/var/run/log"];
+
+/// Facility `user` (1) at severity `info` (6), as `facility * 8 + severity`.
+#[cfg(unix)]
+const PRIORITY: u8 = 14;
+
+/// Frame `message` for the local syslog socket, tagged `tide` with our pid.
+/// The timestamp and hostname are left for the syslog daemon to fill in, as
+/// it does for any message received on its local socket.
+#[cfg(unix)]
+fn frame(message: &str) -> String {
+    format!("<{}>tide[{}]: {}", PRIORITY, std::process::id(), message)
+}
+
+/// Send each message as its own datagram to the first local syslog socket
+/// that accepts a connection. The error is that of the first socket that
+/// exists, since the others are usually missing on this platform.
+#[cfg(unix)]
+pub fn send(messages: &[String]) -> io::Result<()> {
+    let mut first_error = None;
+    for path in SOCKET_PATHS {
+        match send_to(path, messages) {
+            Ok(()) => return Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => {
+                first_error
+                    .get_or_insert_with(|| io::Error::new(e.kind(), format!("{}: {}", path, e)));
+            }
+        }
+    }
+    Err(first_error
+        .unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no syslog socket found")))
+}
+
+#[cfg(unix)]
+fn send_to(path: &str, messages: &[String]) -> io::Result<()> {
+    let socket = std::os::unix::net::UnixDatagram::unbound()?;
+    socket.connect(path)?;
+    for message in messages {
+        socket.send(frame(message).as_bytes())?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn send(_messages: &[String]) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "syslog is only supported on Unix",
+    ))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixDatagram;
+
+    #[test]
+    fn test_send_to_frames_each_message() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log");
+        let daemon = UnixDatagram::bind(&path).unwrap();
+
+        let messages = vec!["requests=10 errors=0".to_string(), "run=b".to_string()];
+        send_to(path.to_str().unwrap(), &messages).unwrap();
+
+        let mut buf = [0u8; 512];
+        let read = daemon.recv(&mut buf).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&buf[..read]).unwrap(),
+            format!("<14>tide[{}]: requests=10 errors=0", std::process::id())
+        );
+        let read = daemon.recv(&mut buf).unwrap();
+        assert!(std::str::from_utf8(&buf[..read])
+            .unwrap()
+            .ends_with("]: run=b"));
+
+        // Nothing listening is an error, not a panic
+        drop(daemon);
+        assert!(send_to(path.to_str().unwrap(), &messages).is_err());
+    }
+}
@@ -0,0 +1,105 @@
+use rand::Rng;
+use std::fmt;
+use std::time::Duration;
+
+/// Longest pause accepted: an hour, far beyond any realistic think time and
+/// well within what `Instant` arithmetic can take.
+const MAX_THINK_TIME_MS: u64 = 3_600_000;
+
+/// A pause a persistent worker takes after each request completes, fixed or
+/// drawn uniformly from a range, in whole milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThinkTime {
+    min: u64,
+    max: u64,
+}
+
+impl ThinkTime {
+    /// Parse `250` for a fixed pause or `100-500` for a random one.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let number = |part: &str| {
+            part.trim()
+                .parse::<u64>()
+                .map_err(|_| format!("Invalid think time: {} (use MS or MIN-MAX)", value))
+        };
+        let (min, max) = match value.split_once('-') {
+            Some((min, max)) => (number(min)?, number(max)?),
+            None => {
+                let ms = number(value)?;
+                (ms, ms)
+            }
+        };
+        if min > max {
+            return Err(format!(
+                "Invalid think time: {} (the minimum is above the maximum)",
+                value
+            ));
+        }
+        if max == 0 {
+            return Err("Think time must be > 0".to_string());
+        }
+        if max > MAX_THINK_TIME_MS {
+            return Err(format!(
+                "Think time must be at most {} ms (one hour)",
+                MAX_THINK_TIME_MS
+            ));
+        }
+        Ok(Self { min, max })
+    }
+
+    /// The next pause.
+    pub fn pick(&self) -> Duration {
+        Duration::from_millis(rand::rng().random_range(self.min..=self.max))
+    }
+
+    /// The average pause over many requests.
+    pub fn mean(&self) -> Duration {
+        Duration::from_micros((self.min + self.max) * 500)
+    }
+}
+
+impl fmt::Display for ThinkTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.min == self.max {
+            write!(f, "{}", self.min)
+        } else {
+            write!(f, "{}-{}", self.min, self.max)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_think_time() {
+        let fixed = ThinkTime::parse("250").unwrap();
+        assert_eq!(fixed.pick(), Duration::from_millis(250));
+        assert_eq!(fixed.to_string(), "250");
+
+        let range = ThinkTime::parse("100-500").unwrap();
+        assert_eq!(range.mean(), Duration::from_millis(300));
+        assert_eq!(range.to_string(), "100-500");
+        for _ in 0..100 {
+            let pause = range.pick();
+            assert!(pause >= Duration::from_millis(100) && pause <= Duration::from_millis(500));
+        }
+
+        assert!(ThinkTime::parse("3600000").is_ok());
+        for value in [
+            "",
+            "fast",
+            "500-100",
+            "-5",
+            "100-",
+            "0",
+            "0-0",
+            "1.5",
+            "3600001",
+            "1-18446744073709551615",
+        ] {
+            assert!(ThinkTime::parse(value).is_err(), "{}", value);
+        }
+    }
+}
@@ -0,0 +1,245 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::stats::{has_enough_samples, percentile};
+
+/// One finished request, placed on the run's timeline.
+#[derive(Debug, Clone, Copy)]
+pub struct Completion {
+    /// When the request finished, relative to the start of the run, measured
+    /// with the monotonic clock
+    pub at: Duration,
+    pub latency: Duration,
+    pub success: bool,
+}
+
+/// Aggregated results for one window of the run.
+#[derive(Debug, PartialEq)]
+pub struct TimeBucket {
+    /// Offset of the window from the start of the run
+    pub start: Duration,
+    /// Wall-clock time the window began: the run's wall-clock start plus the
+    /// monotonic offset, so it lines up with logs without clock-jump artifacts
+    pub started_at: SystemTime,
+    /// Shorter than the window for a partial final bucket
+    pub length: Duration,
+    pub requests: u32,
+    pub errors: u32,
+    pub p50: Option<Duration>,
+    pub p95: Option<Duration>,
+}
+
+impl TimeBucket {
+    pub fn requests_per_second(&self) -> f64 {
+        let secs = self.length.as_secs_f64();
+        if secs == 0.0 {
+            return 0.0;
+        }
+        self.requests as f64 / secs
+    }
+}
+
+/// Split a run of length `elapsed` that began at wall-clock time `started_at`
+/// into consecutive `window`-sized buckets and aggregate the completions
+/// falling in each. The last bucket covers whatever remains, so it may be
+/// shorter than `window`. Every window gets a bucket, including ones where
+/// nothing completed.
+pub fn bucketize(
+    completions: &[Completion],
+    window: Duration,
+    elapsed: Duration,
+    started_at: SystemTime,
+) -> Vec<TimeBucket> {
+    if window.is_zero() || elapsed.is_zero() {
+        return Vec::new();
+    }
+
+    let count = elapsed.as_nanos().div_ceil(window.as_nanos()) as usize;
+    let mut latencies: Vec<Vec<Duration>> = vec![Vec::new(); count];
+    let mut errors = vec![0u32; count];
+
+    for completion in completions {
+        // Requests finishing right at the end belong to the final bucket
+        let index = ((completion.at.as_nanos() / window.as_nanos()) as usize).min(count - 1);
+        latencies[index].push(completion.latency);
+        if !completion.success {
+            errors[index] += 1;
+        }
+    }
+
+    latencies
+        .into_iter()
+        .zip(errors)
+        .enumerate()
+        .map(|(index, (mut latencies, errors))| {
+            let start = window * index as u32;
+            latencies.sort();
+            let count = latencies.len() as u64;
+            let checked = |p: f64| has_enough_samples(count, p).then(|| percentile(&latencies, p));
+
+            TimeBucket {
+                start,
+                started_at: started_at + start,
+                length: window.min(elapsed - start),
+                requests: latencies.len() as u32,
+                errors,
+                p50: checked(50.0),
+                p95: checked(95.0),
+            }
+        })
+        .collect()
+}
+
+/// Latency of the first window compared with the last, to spot a run that
+/// slows down as it goes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LatencyDrift {
+    /// The percentile compared: `p95`, or `p50` when a window is too small
+    pub percentile: &'static str,
+    pub first: Duration,
+    pub last: Duration,
+}
+
+impl LatencyDrift {
+    /// How many times slower the last window was than the first.
+    pub fn ratio(&self) -> f64 {
+        self.last.as_secs_f64() / self.first.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+/// Compare the first and last windows that completed any requests, by p95
+/// when both have enough samples for it and by p50 otherwise. `None` when
+/// there aren't two such windows or either is too small even for a p50.
+pub fn latency_drift(buckets: &[TimeBucket]) -> Option<LatencyDrift> {
+    let mut active = buckets.iter().filter(|bucket| bucket.requests > 0);
+    let first = active.next()?;
+    let last = active.next_back()?;
+
+    let (percentile, first, last) = match (first.p95, last.p95) {
+        (Some(first), Some(last)) => ("p95", first, last),
+        _ => ("p50", first.p50?, last.p50?),
+    };
+    Some(LatencyDrift {
+        percentile,
+        first,
+        last,
+    })
+}
+
+/// Format a wall-clock time as UTC `HH:MM:SS.mmm`.
+pub fn format_clock(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let day_secs = since_epoch.as_secs() % 86_400;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        day_secs / 3600,
+        day_secs % 3600 / 60,
+        day_secs % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn completion(at_ms: u64, success: bool) -> Completion {
+        Completion {
+            at: Duration::from_millis(at_ms),
+            latency: Duration::from_millis(10),
+            success,
+        }
+    }
+
+    #[test]
+    fn test_bucketize_partial_final_bucket() {
+        let completions = [
+            completion(100, true),
+            completion(400, false),
+            completion(600, true),
+            completion(1250, true),
+            completion(1300, true),
+        ];
+
+        let buckets = bucketize(
+            &completions,
+            Duration::from_millis(500),
+            Duration::from_millis(1300),
+            UNIX_EPOCH,
+        );
+
+        let shape: Vec<_> = buckets
+            .iter()
+            .map(|b| {
+                (
+                    b.start.as_millis(),
+                    b.length.as_millis(),
+                    b.requests,
+                    b.errors,
+                )
+            })
+            .collect();
+        assert_eq!(
+            shape,
+            vec![(0, 500, 2, 1), (500, 500, 1, 0), (1000, 300, 2, 0)]
+        );
+        assert!((buckets[2].requests_per_second() - 2.0 / 0.3).abs() < 1e-9);
+        assert_eq!(format_clock(buckets[2].started_at), "00:00:01.000");
+    }
+
+    #[test]
+    fn test_bucketize_keeps_empty_windows() {
+        let buckets = bucketize(
+            &[completion(2500, true)],
+            Duration::from_secs(1),
+            Duration::from_secs(3),
+            UNIX_EPOCH,
+        );
+
+        let requests: Vec<_> = buckets.iter().map(|b| b.requests).collect();
+        assert_eq!(requests, vec![0, 0, 1]);
+        assert_eq!(buckets[0].p50, None);
+    }
+
+    #[test]
+    fn test_latency_drift_compares_first_and_last_active_windows() {
+        let bucket = |requests: u32, p50_ms: u64, p95_ms: Option<u64>| TimeBucket {
+            start: Duration::ZERO,
+            started_at: UNIX_EPOCH,
+            length: Duration::from_secs(1),
+            requests,
+            errors: 0,
+            p50: Some(Duration::from_millis(p50_ms)),
+            p95: p95_ms.map(Duration::from_millis),
+        };
+
+        let buckets = [
+            bucket(40, 10, Some(20)),
+            bucket(40, 12, Some(25)),
+            bucket(40, 20, Some(50)),
+            bucket(0, 0, None),
+        ];
+        let drift = latency_drift(&buckets).unwrap();
+        assert_eq!(drift.percentile, "p95");
+        assert_eq!(drift.ratio(), 2.5);
+
+        // A small final window falls back to the median
+        let drift = latency_drift(&[bucket(40, 10, Some(20)), bucket(5, 15, None)]).unwrap();
+        assert_eq!(
+            (drift.percentile, drift.first, drift.last),
+            ("p50", Duration::from_millis(10), Duration::from_millis(15))
+        );
+
+        assert_eq!(latency_drift(&[bucket(40, 10, Some(20))]), None);
+    }
+
+    #[test]
+    fn test_format_clock() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_700_000_000_250);
+        assert_eq!(format_clock(time), "22:13:20.250");
+    }
+
+    #[test]
+    fn test_bucketize_empty_run() {
+        assert!(bucketize(&[], Duration::from_secs(1), Duration::ZERO, UNIX_EPOCH).is_empty());
+    }
+}
@@ -1,4 +1,5 @@
 use assert_cmd::Command;
+use predicates::prelude::*;
 
 // Basic integration tests for the CLI application
 
@@ -57,3 +58,21 @@ fn test_app_with_timeout() {
 
     result.success();
 }
+
+#[test]
+fn test_app_with_oneline() {
+    // The compact summary should be a single key=value line
+    let result = Command::cargo_bin("tide")
+        .unwrap()
+        .arg("--url")
+        .arg("https://example.com")
+        .arg("--duration")
+        .arg("1")
+        .arg("--oneline")
+        .assert();
+
+    result
+        .success()
+        .stdout(predicate::str::contains("err="))
+        .stdout(predicate::str::contains("*** Summary Report ***").not());
+}